@@ -101,14 +101,15 @@ impl<'a> ChrootHelper<'a> {
 
 #[derive(Debug, Clone)]
 pub struct Builder<'a> {
-    signer: Option<super::signer::Signer<'a>>,
+    signer: Option<&'a dyn super::signer::Signer>,
     srcdest: &'a std::path::Path,
     logdest: &'a std::path::Path,
+    linter: Option<super::lint::Linter>,
 }
 
 impl<'a> Builder<'a> {
     pub fn new(
-        signer: Option<super::signer::Signer<'a>>,
+        signer: Option<&'a dyn super::signer::Signer>,
         srcdest: &'a std::path::Path,
         logdest: &'a std::path::Path,
     ) -> Self {
@@ -116,9 +117,15 @@ impl<'a> Builder<'a> {
             signer,
             srcdest,
             logdest,
+            linter: None,
         }
     }
 
+    /// Lint built packages and refuse to copy them into the repository on errors
+    pub fn set_linter(&mut self, linter: super::lint::Linter) {
+        self.linter = Some(linter);
+    }
+
     pub async fn build_package<P, Q>(
         &self,
         package_dir: P,
@@ -136,8 +143,24 @@ impl<'a> Builder<'a> {
             .makechrootpkg(package_dir, self.srcdest, pkgdest, self.logdest)
             .await?;
         let mut dir = tokio::fs::read_dir(pkgdest).await?;
-        let mut futures_unordered = futures::stream::FuturesUnordered::new();
+        let mut entries = vec![];
         while let Some(entry) = dir.next_entry().await? {
+            entries.push(entry);
+        }
+        if let Some(linter) = &self.linter {
+            for entry in &entries {
+                let path = entry.path();
+                let linter = linter.clone();
+                // Loading decompresses and hashes the whole package
+                tokio::task::spawn_blocking(move || {
+                    let package = super::package::Package::load(path)?;
+                    linter.check(&package)
+                })
+                .await??;
+            }
+        }
+        let mut futures_unordered = futures::stream::FuturesUnordered::new();
+        for entry in entries {
             let dest = repo_dir.as_ref().join(entry.file_name());
             futures_unordered.push(async move {
                 let symlink_package_path = package_dir.join(entry.file_name());
@@ -155,7 +178,7 @@ impl<'a> Builder<'a> {
                 if let Some(signer) = &self.signer {
                     let mut sig_dest = dest.clone().into_os_string();
                     sig_dest.push(".sig");
-                    signer.sign(&dest, sig_dest).await?;
+                    signer.sign(&dest, std::path::Path::new(&sig_dest)).await?;
                 }
                 Ok::<_, anyhow::Error>(dest)
             });
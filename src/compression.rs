@@ -0,0 +1,549 @@
+use std::io::{Read, Write};
+
+/// Compression formats makepkg can emit via `PKGEXT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum Compression {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "gz")]
+    Gzip,
+    #[serde(rename = "bz2")]
+    Bzip2,
+    #[serde(rename = "xz")]
+    Xz,
+    #[serde(rename = "zst")]
+    Zstd,
+    #[serde(rename = "lz4")]
+    Lz4,
+    #[serde(rename = "lz")]
+    Lzip,
+    #[serde(rename = "Z")]
+    Compress,
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Compression::None => "none".fmt(f),
+            Compression::Gzip => "gz".fmt(f),
+            Compression::Bzip2 => "bz2".fmt(f),
+            Compression::Xz => "xz".fmt(f),
+            Compression::Zstd => "zst".fmt(f),
+            Compression::Lz4 => "lz4".fmt(f),
+            Compression::Lzip => "lz".fmt(f),
+            Compression::Compress => "Z".fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "gz" => Ok(Self::Gzip),
+            "bz2" => Ok(Self::Bzip2),
+            "xz" => Ok(Self::Xz),
+            "zst" => Ok(Self::Zstd),
+            "lz4" => Ok(Self::Lz4),
+            "lz" => Ok(Self::Lzip),
+            "Z" => Ok(Self::Compress),
+            _ => anyhow::bail!("Unknown compression: {s}"),
+        }
+    }
+}
+
+/// Returned when the input does not start with any known magic bytes
+#[derive(Debug)]
+pub struct UnknownFormatError;
+
+impl std::fmt::Display for UnknownFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        "Unknown compression format".fmt(f)
+    }
+}
+
+impl std::error::Error for UnknownFormatError {}
+
+// Enough to cover the ustar magic of an uncompressed tar header
+const HEADER_SIZE: u64 = 512;
+
+impl Compression {
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"\x1f\x8b") {
+            Some(Compression::Gzip)
+        } else if header.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if header.starts_with(b"\xfd7zXZ\x00") {
+            Some(Compression::Xz)
+        } else if header.starts_with(b"\x28\xb5\x2f\xfd") {
+            Some(Compression::Zstd)
+        } else if header.starts_with(b"\x04\x22\x4d\x18") {
+            Some(Compression::Lz4)
+        } else if header.starts_with(b"LZIP") {
+            Some(Compression::Lzip)
+        } else if header.starts_with(b"\x1f\x9d") {
+            Some(Compression::Compress)
+        } else if header.len() >= 262 && &header[257..262] == b"ustar" {
+            Some(Compression::None)
+        } else if header.len() == HEADER_SIZE as usize && header.iter().all(|&b| b == 0) {
+            // An empty tar archive consists of zero blocks only
+            Some(Compression::None)
+        } else {
+            None
+        }
+    }
+
+    /// Whether [`compress`] can write this format
+    pub fn is_writable(&self) -> bool {
+        matches!(
+            *self,
+            Compression::None | Compression::Gzip | Compression::Xz | Compression::Zstd
+        )
+    }
+
+    /// File extension used by repo-add, e.g. `tar.zst` for `foo.db.tar.zst`
+    pub fn tar_extension(&self) -> String {
+        match *self {
+            Compression::None => "tar".to_owned(),
+            _ => format!("tar.{}", self),
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match *self {
+            Compression::None => "application/x-tar",
+            Compression::Gzip => "application/gzip",
+            Compression::Bzip2 => "application/x-bzip2",
+            Compression::Xz => "application/x-xz",
+            Compression::Zstd => "application/zstd",
+            Compression::Lz4 => "application/x-lz4",
+            Compression::Lzip => "application/x-lzip",
+            Compression::Compress => "application/x-compress",
+        }
+    }
+}
+
+/// Writer compressing the bytes written to it, created by [`compress`]
+pub enum Encoder<W>
+where
+    W: Write,
+{
+    None(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Xz(lzma::LzmaWriter<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W> Write for Encoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Encoder::None(w) => w.write(buf),
+            Encoder::Gzip(w) => w.write(buf),
+            Encoder::Xz(w) => w.write(buf),
+            Encoder::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Encoder::None(w) => w.flush(),
+            Encoder::Gzip(w) => w.flush(),
+            Encoder::Xz(w) => w.flush(),
+            Encoder::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    pub fn finish(self) -> Result<W, anyhow::Error> {
+        Ok(match self {
+            Encoder::None(w) => w,
+            Encoder::Gzip(w) => w.finish()?,
+            Encoder::Xz(w) => w.finish()?,
+            Encoder::Zstd(w) => w.finish()?,
+        })
+    }
+}
+
+/// Compress bytes written to `writer` with `compression`.
+/// Only formats used for repository databases by repo-add are supported.
+pub fn compress<W>(
+    writer: W,
+    compression: Compression,
+    level: Option<u32>,
+) -> Result<Encoder<W>, anyhow::Error>
+where
+    W: Write,
+{
+    Ok(match compression {
+        Compression::None => Encoder::None(writer),
+        Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+            writer,
+            level.map_or_else(flate2::Compression::default, flate2::Compression::new),
+        )),
+        Compression::Xz => Encoder::Xz(lzma::LzmaWriter::new_compressor(
+            writer,
+            level.unwrap_or(6),
+        )?),
+        Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(
+            writer,
+            level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| l as i32),
+        )?),
+        _ => anyhow::bail!("Unsupported compression for writing: {}", compression),
+    })
+}
+
+/// Sniff the compression of `reader` and return a reader yielding decompressed bytes
+pub fn decompress<'a, R>(mut reader: R) -> Result<Box<dyn Read + 'a>, anyhow::Error>
+where
+    R: Read + 'a,
+{
+    let mut header = vec![];
+    reader.by_ref().take(HEADER_SIZE).read_to_end(&mut header)?;
+    let compression = Compression::detect(&header).ok_or(UnknownFormatError)?;
+    let reader = std::io::Cursor::new(header).chain(reader);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        Compression::Xz => Box::new(lzma::LzmaReader::new_decompressor(reader)?),
+        Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        Compression::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+        Compression::Lzip => Box::new(std::io::Cursor::new(decompress_lzip(reader)?)),
+        Compression::Compress => Box::new(std::io::Cursor::new(decompress_lzw(reader)?)),
+    })
+}
+
+// lzip members can only be delimited by their trailers, so the whole input is read into memory.
+// These packages are rare enough that streaming them is not worth it.
+fn decompress_lzip<R>(mut reader: R) -> Result<Vec<u8>, anyhow::Error>
+where
+    R: Read,
+{
+    const HEADER_LEN: usize = 6;
+    const TRAILER_LEN: usize = 20;
+
+    let mut input = vec![];
+    reader.read_to_end(&mut input)?;
+
+    // Members are located from the end of the input, as recommended by the lzip manual
+    let mut members = vec![];
+    let mut end = input.len();
+    while end > 0 {
+        if end < HEADER_LEN + TRAILER_LEN {
+            return Err(anyhow::anyhow!("Truncated lzip member"));
+        }
+        let member_size = u64_le(&input[end - 8..end]) as usize;
+        if member_size < HEADER_LEN + TRAILER_LEN || member_size > end {
+            return Err(anyhow::anyhow!("Invalid lzip member size {}", member_size));
+        }
+        members.push(&input[end - member_size..end]);
+        end -= member_size;
+    }
+
+    let mut output = vec![];
+    for member in members.into_iter().rev() {
+        if !member.starts_with(b"LZIP") || member[4] != 1 {
+            return Err(anyhow::anyhow!("Invalid lzip header"));
+        }
+        let dict_byte = member[5];
+        let mut dict_size = 1u32 << (dict_byte & 0x1f);
+        dict_size -= (dict_size / 16) * ((dict_byte >> 5) & 0x07) as u32;
+
+        let trailer = &member[member.len() - TRAILER_LEN..];
+        let params = lzma_rs::decompress::raw::LzmaParams::new(
+            lzma_rs::decompress::raw::LzmaProperties {
+                lc: 3,
+                lp: 0,
+                pb: 2,
+            },
+            dict_size,
+            None,
+        );
+        let mut decoder = lzma_rs::decompress::raw::LzmaDecoder::new(params, None)
+            .map_err(|e| anyhow::anyhow!("Unable to initialize lzip decoder: {}", e))?;
+        let mut data = vec![];
+        decoder
+            .decompress(
+                &mut &member[HEADER_LEN..member.len() - TRAILER_LEN],
+                &mut data,
+            )
+            .map_err(|e| anyhow::anyhow!("Unable to decompress lzip member: {}", e))?;
+
+        let mut crc = flate2::Crc::new();
+        crc.update(&data);
+        if crc.sum() != u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) {
+            return Err(anyhow::anyhow!("lzip CRC mismatch"));
+        }
+        if data.len() as u64 != u64_le(&trailer[4..12]) {
+            return Err(anyhow::anyhow!("lzip data size mismatch"));
+        }
+        output.extend_from_slice(&data);
+    }
+    Ok(output)
+}
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+// Decoder for compress(1) .Z files, following the behavior of ncompress
+fn decompress_lzw<R>(mut reader: R) -> Result<Vec<u8>, anyhow::Error>
+where
+    R: Read,
+{
+    const INIT_BITS: u32 = 9;
+    const CLEAR: usize = 256;
+
+    let mut input = vec![];
+    reader.read_to_end(&mut input)?;
+    if input.len() < 3 || !input.starts_with(b"\x1f\x9d") {
+        return Err(anyhow::anyhow!("Invalid compress header"));
+    }
+    let max_bits = (input[2] & 0x1f) as u32;
+    let block_mode = input[2] & 0x80 != 0;
+    if !(INIT_BITS..=16).contains(&max_bits) {
+        return Err(anyhow::anyhow!("Unsupported compress bits {}", max_bits));
+    }
+    let data = &input[3..];
+    let total_bits = data.len() * 8;
+    let max_max_code = 1usize << max_bits;
+
+    let mut prefix = vec![0usize; max_max_code];
+    let mut suffix: Vec<u8> = (0..max_max_code).map(|c| c as u8).collect();
+    let mut n_bits = INIT_BITS;
+    let mut max_code = (1usize << n_bits) - 1;
+    let mut free_ent = if block_mode { CLEAR + 1 } else { CLEAR };
+    // Codes are written in groups of 8, and a group is padded whenever the code width changes
+    let mut pos = 0;
+    let mut group_start = 0;
+    let mut old_code = None;
+    let mut fin_char = 0;
+    let mut stack = vec![];
+    let mut output = vec![];
+
+    loop {
+        if free_ent > max_code {
+            pos = align_group(pos, group_start, n_bits);
+            group_start = pos;
+            n_bits += 1;
+            max_code = if n_bits == max_bits {
+                max_max_code
+            } else {
+                (1 << n_bits) - 1
+            };
+        }
+        if pos + n_bits as usize > total_bits {
+            break;
+        }
+        let mut code = 0;
+        for i in 0..n_bits as usize {
+            let bit = pos + i;
+            code |= ((data[bit / 8] >> (bit % 8)) as usize & 1) << i;
+        }
+        pos += n_bits as usize;
+
+        let prev = match old_code {
+            None => {
+                if code > 255 {
+                    return Err(anyhow::anyhow!("Corrupt compress input"));
+                }
+                fin_char = code as u8;
+                old_code = Some(code);
+                output.push(fin_char);
+                continue;
+            }
+            Some(prev) => prev,
+        };
+        if code == CLEAR && block_mode {
+            pos = align_group(pos, group_start, n_bits);
+            group_start = pos;
+            free_ent = CLEAR;
+            n_bits = INIT_BITS;
+            max_code = (1 << n_bits) - 1;
+            continue;
+        }
+
+        let in_code = code;
+        if code >= free_ent {
+            if code > free_ent {
+                return Err(anyhow::anyhow!("Corrupt compress input"));
+            }
+            stack.push(fin_char);
+            code = prev;
+        }
+        while code > 255 {
+            stack.push(suffix[code]);
+            code = prefix[code];
+        }
+        fin_char = suffix[code];
+        stack.push(fin_char);
+        output.extend(stack.drain(..).rev());
+
+        if free_ent < max_max_code {
+            prefix[free_ent] = prev;
+            suffix[free_ent] = fin_char;
+            free_ent += 1;
+        }
+        old_code = Some(in_code);
+    }
+    Ok(output)
+}
+
+fn align_group(pos: usize, group_start: usize, n_bits: u32) -> usize {
+    let group_bits = n_bits as usize * 8;
+    let offset = pos - group_start;
+    group_start + offset.div_ceil(group_bits) * group_bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, decompress_lzip, decompress_lzw};
+    use std::io::Read as _;
+
+    const WORDS: &[u8] = include_bytes!("../tests/fixtures/words.txt");
+
+    fn decompress_all(input: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        decompress(input).unwrap().read_to_end(&mut output).unwrap();
+        output
+    }
+
+    // Packs codes LSB first like compress(1)
+    struct CodeWriter {
+        bytes: Vec<u8>,
+        pos: usize,
+    }
+
+    impl CodeWriter {
+        fn new(max_bits: u8) -> Self {
+            CodeWriter {
+                bytes: vec![0x1f, 0x9d, 0x80 | max_bits],
+                pos: 0,
+            }
+        }
+
+        fn write(&mut self, code: usize, n_bits: usize) {
+            for i in 0..n_bits {
+                let bit = self.pos + i;
+                if bit / 8 + 3 >= self.bytes.len() {
+                    self.bytes.push(0);
+                }
+                self.bytes[bit / 8 + 3] |= ((code >> i) as u8 & 1) << (bit % 8);
+            }
+            self.pos += n_bits;
+        }
+
+        fn pad_to(&mut self, pos: usize) {
+            assert!(self.pos <= pos);
+            self.bytes.resize(pos.div_ceil(8) + 3, 0);
+            self.pos = pos;
+        }
+    }
+
+    // Produced by `bsdtar --format raw -Z`, which writes 16-bit codes in block mode
+    #[test]
+    fn test_compress() {
+        assert_eq!(
+            decompress_all(include_bytes!("../tests/fixtures/words.txt.Z")),
+            WORDS
+        );
+    }
+
+    #[test]
+    fn test_compress_clear() {
+        let mut writer = CodeWriter::new(9);
+        writer.write(b'a' as usize, 9);
+        writer.write(b'b' as usize, 9);
+        writer.write(256, 9);
+        // CLEAR skips the rest of the group of 8 codes
+        writer.pad_to(9 * 8);
+        writer.write(b'c' as usize, 9);
+        writer.write(b'd' as usize, 9);
+        // 257 was "ab" before CLEAR, and is "cd" after that
+        writer.write(257, 9);
+        assert_eq!(decompress_lzw(writer.bytes.as_slice()).unwrap(), b"abcdcd");
+    }
+
+    #[test]
+    fn test_compress_code_width_reset() {
+        let mut writer = CodeWriter::new(10);
+        for byte in 0..=255 {
+            writer.write(byte, 9);
+        }
+        // 256 codes fill the table up to 511, which happens to end a group of 9-bit codes
+        writer.write(b'x' as usize, 10);
+        writer.write(256, 10);
+        writer.pad_to(9 * 256 + 10 * 8);
+        writer.write(b'y' as usize, 9);
+        writer.write(b'z' as usize, 9);
+
+        let mut expected: Vec<u8> = (0..=255).collect();
+        expected.extend_from_slice(b"xyz");
+        assert_eq!(decompress_lzw(writer.bytes.as_slice()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_compress_malformed() {
+        let mut writer = CodeWriter::new(9);
+        writer.write(b'a' as usize, 9);
+        // Only 257 can be defined by the code itself
+        writer.write(258, 9);
+        assert!(decompress_lzw(writer.bytes.as_slice()).is_err());
+
+        let mut writer = CodeWriter::new(9);
+        writer.write(257, 9);
+        assert!(decompress_lzw(writer.bytes.as_slice()).is_err());
+
+        assert!(decompress_lzw(&b"\x1f\x9d\x91"[..]).is_err());
+        assert!(decompress_lzw(&b"\x1f\x9d"[..]).is_err());
+    }
+
+    // Produced by `bsdtar --format raw --lzip`
+    #[test]
+    fn test_lzip() {
+        assert_eq!(
+            decompress_all(include_bytes!("../tests/fixtures/words.txt.lz")),
+            WORDS
+        );
+    }
+
+    // Two members produced separately and concatenated
+    #[test]
+    fn test_lzip_multi_member() {
+        assert_eq!(
+            decompress_all(include_bytes!("../tests/fixtures/words-multi.txt.lz")),
+            WORDS
+        );
+    }
+
+    #[test]
+    fn test_lzip_malformed() {
+        let input = include_bytes!("../tests/fixtures/words.txt.lz");
+        assert!(decompress_lzip(&input[..input.len() - 1]).is_err());
+
+        let mut corrupted = input.to_vec();
+        let crc_pos = corrupted.len() - 20;
+        corrupted[crc_pos] ^= 0xff;
+        assert_eq!(
+            decompress_lzip(corrupted.as_slice())
+                .unwrap_err()
+                .to_string(),
+            "lzip CRC mismatch"
+        );
+
+        let mut corrupted = input.to_vec();
+        corrupted[4] = 0;
+        assert!(decompress_lzip(corrupted.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::Context as _;
+use md5::Digest as _;
+use std::io::Write as _;
+
+// Files generated from the package description instead of copied from the staging directory
+const METADATA_FILES: [&str; 2] = [".MTREE", ".PKGINFO"];
+
+/// File in the staging directory to be archived
+#[derive(Debug)]
+struct StagedFile {
+    path: std::path::PathBuf,
+    metadata: std::fs::Metadata,
+}
+
+/// Create `{pkgname}-{pkgver}-{arch}.pkg.tar.zst` in `out_dir` from files in `staging_dir`, like
+/// makepkg does from `$pkgdir`. The size in `pkginfo` is replaced with the installed size of the
+/// files, and all files are owned by root in the package.
+pub fn create_package<P, Q>(
+    pkginfo: &super::package::PkgInfo,
+    staging_dir: P,
+    out_dir: Q,
+) -> Result<std::path::PathBuf, anyhow::Error>
+where
+    P: AsRef<std::path::Path>,
+    Q: AsRef<std::path::Path>,
+{
+    let staging_dir = staging_dir.as_ref();
+    for (key, val) in [
+        ("pkgname", &pkginfo.pkgname),
+        ("pkgver", &pkginfo.pkgver),
+        ("arch", &pkginfo.arch),
+    ] {
+        if val.is_empty() {
+            anyhow::bail!("{} is required to create a package", key);
+        }
+        // They make up the filename of the package
+        if val.contains('/') {
+            anyhow::bail!("{} must not contain /: {}", key, val);
+        }
+    }
+    if pkginfo.pkgname.starts_with('-') {
+        anyhow::bail!("pkgname must not start with -: {}", pkginfo.pkgname);
+    }
+    let version = super::version::Version::parse(&pkginfo.pkgver);
+    if version.pkgver().is_empty() || version.pkgrel().is_none_or(str::is_empty) {
+        anyhow::bail!(
+            "pkgver must be in the form of [epoch:]pkgver-pkgrel: {}",
+            pkginfo.pkgver
+        );
+    }
+
+    let mut files = vec![];
+    collect_files(staging_dir, std::path::Path::new(""), &mut files)
+        .with_context(|| format!("Unable to read directory {}", staging_dir.display()))?;
+    // .BUILDINFO describes the makepkg build environment, which cannot be generated here
+    if files
+        .iter()
+        .any(|file| file.path.as_os_str() == ".BUILDINFO")
+    {
+        anyhow::bail!(
+            ".BUILDINFO in {} is not supported; remove it to create a package without it",
+            staging_dir.display()
+        );
+    }
+    files.retain(|file| {
+        !METADATA_FILES
+            .iter()
+            .any(|name| file.path.as_os_str() == *name)
+    });
+
+    let mut pkginfo = pkginfo.clone();
+    pkginfo.size = files
+        .iter()
+        .filter(|file| file.metadata.is_file())
+        .map(|file| file.metadata.len())
+        .sum();
+    let pkginfo_body = super::package::into_pkginfo_file(&pkginfo);
+    let mtree_body = write_mtree(staging_dir, &files, &pkginfo_body, pkginfo.builddate)?;
+
+    let path = out_dir.as_ref().join(format!(
+        "{}-{}-{}.pkg.tar.zst",
+        pkginfo.pkgname, pkginfo.pkgver, pkginfo.arch
+    ));
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Unable to create {}", path.display()))?;
+    let writer = super::compression::compress(file, super::compression::Compression::Zstd, None)?;
+    let mut builder = tar::Builder::new(writer);
+
+    // makepkg archives files in the C locale order, where .MTREE and .PKGINFO come first
+    let generated = [
+        (".MTREE", mtree_body.as_slice()),
+        (".PKGINFO", pkginfo_body.as_slice()),
+    ];
+    let mut generated = generated.iter().copied().peekable();
+    for file in &files {
+        while let Some((name, body)) =
+            generated.next_if(|(name, _)| std::ffi::OsStr::new(name) < file.path.as_os_str())
+        {
+            append_generated(&mut builder, name, body, pkginfo.builddate)?;
+        }
+        append_file(&mut builder, staging_dir, file)
+            .with_context(|| format!("Unable to archive {}", file.path.display()))?;
+    }
+    for (name, body) in generated {
+        append_generated(&mut builder, name, body, pkginfo.builddate)?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(path)
+}
+
+// Collect files under `dir` recursively, sorted by their paths in bytes
+fn collect_files(
+    root: &std::path::Path,
+    dir: &std::path::Path,
+    files: &mut Vec<StagedFile>,
+) -> Result<(), anyhow::Error> {
+    for entry in std::fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let metadata = entry.path().symlink_metadata()?;
+        let is_dir = metadata.is_dir();
+        files.push(StagedFile {
+            path: path.clone(),
+            metadata,
+        });
+        if is_dir {
+            collect_files(root, &path, files)?;
+        }
+    }
+    // Sort the whole list at last since usr-foo comes before usr/bin in bytes
+    if dir.as_os_str().is_empty() {
+        use std::os::unix::ffi::OsStrExt as _;
+        files.sort_by(|a, b| {
+            a.path
+                .as_os_str()
+                .as_bytes()
+                .cmp(b.path.as_os_str().as_bytes())
+        });
+    }
+    Ok(())
+}
+
+fn write_mtree(
+    staging_dir: &std::path::Path,
+    files: &[StagedFile],
+    pkginfo_body: &[u8],
+    builddate: u64,
+) -> Result<Vec<u8>, anyhow::Error> {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let mut lines = vec![format!(
+        "./.PKGINFO time={}.0 mode=644 type=file uid=0 gid=0 size={} md5digest={:x} sha256digest={:x}",
+        builddate,
+        pkginfo_body.len(),
+        md5::Md5::digest(pkginfo_body),
+        sha2::Sha256::digest(pkginfo_body),
+    )];
+    for file in files {
+        let mut line = format!(
+            "./{} time={}.0 mode={:o} uid=0 gid=0",
+            super::package::mtree_escape(&file.path),
+            file.metadata.mtime(),
+            file.metadata.mode() & 0o7777,
+        );
+        let file_type = file.metadata.file_type();
+        if file_type.is_dir() {
+            line.push_str(" type=dir");
+        } else if file_type.is_symlink() {
+            let target = std::fs::read_link(staging_dir.join(&file.path))?;
+            line.push_str(&format!(
+                " type=link link={}",
+                super::package::mtree_escape(&target)
+            ));
+        } else if file_type.is_file() {
+            let body = std::fs::read(staging_dir.join(&file.path))?;
+            line.push_str(&format!(
+                " type=file size={} md5digest={:x} sha256digest={:x}",
+                body.len(),
+                md5::Md5::digest(&body),
+                sha2::Sha256::digest(&body),
+            ));
+        } else {
+            anyhow::bail!("Unsupported file type of {}", file.path.display());
+        }
+        lines.push(line);
+    }
+    lines.sort();
+
+    let mut encoder =
+        super::compression::compress(vec![], super::compression::Compression::Gzip, None)?;
+    writeln!(encoder, "#mtree")?;
+    for line in lines {
+        writeln!(encoder, "{}", line)?;
+    }
+    encoder.finish()
+}
+
+fn root_header(mode: u32, mtime: u64) -> Result<tar::Header, anyhow::Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(mode);
+    header.set_mtime(mtime);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_username("root")?;
+    header.set_groupname("root")?;
+    Ok(header)
+}
+
+fn append_generated<W>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    body: &[u8],
+    builddate: u64,
+) -> Result<(), anyhow::Error>
+where
+    W: std::io::Write,
+{
+    let mut header = root_header(0o644, builddate)?;
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(body.len() as u64);
+    builder.append_data(&mut header, name, body)?;
+    Ok(())
+}
+
+fn append_file<W>(
+    builder: &mut tar::Builder<W>,
+    staging_dir: &std::path::Path,
+    file: &StagedFile,
+) -> Result<(), anyhow::Error>
+where
+    W: std::io::Write,
+{
+    use std::os::unix::ffi::OsStrExt as _;
+    use std::os::unix::fs::MetadataExt as _;
+
+    let mut header = root_header(file.metadata.mode() & 0o7777, file.metadata.mtime() as u64)?;
+    let file_type = file.metadata.file_type();
+    if file_type.is_dir() {
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        // Store directories with a trailing slash like bsdtar, which Header::set_path strips
+        let mut name = file.path.clone().into_os_string();
+        name.push("/");
+        let bytes = name.as_bytes();
+        if bytes.len() <= header.as_old().name.len() {
+            header.as_old_mut().name[..bytes.len()].copy_from_slice(bytes);
+            header.set_cksum();
+            builder.append(&header, std::io::empty())?;
+        } else {
+            // GNU long names keep the trailing slash
+            builder.append_data(&mut header, name, std::io::empty())?;
+        }
+    } else if file_type.is_symlink() {
+        let target = std::fs::read_link(staging_dir.join(&file.path))?;
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, &file.path, target)?;
+    } else if file_type.is_file() {
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(file.metadata.len());
+        builder.append_data(
+            &mut header,
+            &file.path,
+            std::fs::File::open(staging_dir.join(&file.path))?,
+        )?;
+    } else {
+        anyhow::bail!("Unsupported file type of {}", file.path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::create_package;
+    use crate::package::{Package, PkgInfo};
+
+    fn pkginfo() -> PkgInfo {
+        PkgInfo {
+            pkgname: "foo".to_owned(),
+            pkgver: "1.0-1".to_owned(),
+            arch: "x86_64".to_owned(),
+            builddate: 1_600_000_000,
+            ..PkgInfo::default()
+        }
+    }
+
+    fn staging_dir() -> tempfile::TempDir {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("usr/bin")).unwrap();
+        std::fs::create_dir(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/foo.conf"), "foo = 1\n").unwrap();
+        std::fs::write(root.join("usr/bin/foo"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(
+            root.join("usr/bin/foo"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("foo", root.join("usr/bin/foo-bar")).unwrap();
+        std::fs::write(root.join("usr-foo"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_create_package() {
+        let staging = staging_dir();
+        // Stale metadata in the staging directory is replaced
+        std::fs::write(staging.path().join(".PKGINFO"), "pkgname = bar\n").unwrap();
+        let out_dir = tempfile::tempdir().unwrap();
+        let path = create_package(&pkginfo(), staging.path(), out_dir.path()).unwrap();
+        assert_eq!(path, out_dir.path().join("foo-1.0-1-x86_64.pkg.tar.zst"));
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut archive = tar::Archive::new(crate::compression::decompress(file).unwrap());
+        let members: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                assert_eq!(entry.header().uid().unwrap(), 0);
+                assert_eq!(entry.header().gid().unwrap(), 0);
+                String::from_utf8(entry.path_bytes().into_owned()).unwrap()
+            })
+            .collect();
+        // Paths are sorted without the trailing slash like `find | LC_ALL=C sort` of makepkg
+        assert_eq!(
+            members,
+            [
+                ".MTREE",
+                ".PKGINFO",
+                "etc/",
+                "etc/foo.conf",
+                "usr/",
+                "usr-foo",
+                "usr/bin/",
+                "usr/bin/foo",
+                "usr/bin/foo-bar",
+            ]
+        );
+
+        let package = Package::load(&path).unwrap();
+        assert_eq!(package.pkgname(), "foo");
+        assert_eq!(package.isize(), 18);
+        assert_eq!(package.mtree_error(), None);
+        assert_eq!(package.mtree().unwrap().len(), 8);
+        assert!(crate::verify::verify_package_contents(&package, &path)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_create_package_with_buildinfo() {
+        let staging = staging_dir();
+        std::fs::write(staging.path().join(".BUILDINFO"), "format = 2\n").unwrap();
+        let out_dir = tempfile::tempdir().unwrap();
+        assert!(create_package(&pkginfo(), staging.path(), out_dir.path()).is_err());
+        assert_eq!(std::fs::read_dir(out_dir.path()).unwrap().count(), 0);
+    }
+}
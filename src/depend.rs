@@ -0,0 +1,226 @@
+/// Comparison operator of a dependency, e.g. `>=` of `foo>=1.0`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl std::fmt::Display for DependOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            DependOp::Eq => "=".fmt(f),
+            DependOp::Lt => "<".fmt(f),
+            DependOp::Le => "<=".fmt(f),
+            DependOp::Gt => ">".fmt(f),
+            DependOp::Ge => ">=".fmt(f),
+        }
+    }
+}
+
+/// Entry of depends, provides, conflicts and so on in the form of
+/// `name[<op><version>][: description]`, parsed like alpm_dep_from_string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depend {
+    name: String,
+    constraint: Option<(DependOp, String)>,
+    description: Option<String>,
+}
+
+impl Depend {
+    pub fn parse(s: &str) -> Self {
+        let (spec, description) = match s.split_once(": ") {
+            Some((spec, description)) => (spec, Some(description.to_owned())),
+            None => (s, None),
+        };
+        let (name, constraint) = match spec.find(['<', '>', '=']) {
+            Some(i) => {
+                let (op, len) = if spec[i..].starts_with(">=") {
+                    (DependOp::Ge, 2)
+                } else if spec[i..].starts_with("<=") {
+                    (DependOp::Le, 2)
+                } else if spec[i..].starts_with('=') {
+                    (DependOp::Eq, 1)
+                } else if spec[i..].starts_with('<') {
+                    (DependOp::Lt, 1)
+                } else {
+                    (DependOp::Gt, 1)
+                };
+                (&spec[..i], Some((op, spec[i + len..].to_owned())))
+            }
+            None => (spec, None),
+        };
+        Depend {
+            name: name.to_owned(),
+            constraint,
+            description,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn op(&self) -> Option<DependOp> {
+        self.constraint.as_ref().map(|(op, _)| *op)
+    }
+    pub fn version(&self) -> Option<&str> {
+        self.constraint
+            .as_ref()
+            .map(|(_, version)| version.as_str())
+    }
+    /// Description of optdepends
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Whether `version` meets the version constraint, if any
+    pub fn matches_version(&self, version: &str) -> bool {
+        use std::cmp::Ordering;
+
+        match &self.constraint {
+            None => true,
+            Some((op, required)) => {
+                let ordering = super::version::vercmp(version, required);
+                match op {
+                    DependOp::Eq => ordering == Ordering::Equal,
+                    DependOp::Lt => ordering == Ordering::Less,
+                    DependOp::Le => ordering != Ordering::Greater,
+                    DependOp::Gt => ordering == Ordering::Greater,
+                    DependOp::Ge => ordering != Ordering::Less,
+                }
+            }
+        }
+    }
+
+    /// Whether a package of `pkgname` and `pkgver` providing `provides` satisfies the dependency.
+    /// Like alpm, a versioned dependency is satisfied only by provides with `=version`.
+    pub fn satisfied_by(&self, pkgname: &str, pkgver: &str, provides: &[Depend]) -> bool {
+        if pkgname == self.name && self.matches_version(pkgver) {
+            return true;
+        }
+        provides.iter().any(|provide| {
+            provide.name == self.name
+                && match (&self.constraint, &provide.constraint) {
+                    (None, _) => true,
+                    (Some(_), Some((DependOp::Eq, version))) => self.matches_version(version),
+                    (Some(_), _) => false,
+                }
+        })
+    }
+}
+
+impl std::fmt::Display for Depend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        self.name.fmt(f)?;
+        if let Some((op, version)) = &self.constraint {
+            write!(f, "{}{}", op, version)?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, ": {}", description)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_all(xs: &[String]) -> Vec<Depend> {
+    xs.iter().map(|x| Depend::parse(x)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_all, Depend, DependOp};
+
+    #[test]
+    fn test_parse() {
+        for &(s, name, op, version, description) in &[
+            ("foo", "foo", None, None, None),
+            ("foo=1.0-1", "foo", Some(DependOp::Eq), Some("1.0-1"), None),
+            ("foo<1.0", "foo", Some(DependOp::Lt), Some("1.0"), None),
+            ("foo<=1.0", "foo", Some(DependOp::Le), Some("1.0"), None),
+            ("foo>1.0", "foo", Some(DependOp::Gt), Some("1.0"), None),
+            ("foo>=1:1.0", "foo", Some(DependOp::Ge), Some("1:1.0"), None),
+            (
+                "libfoo.so=1-64",
+                "libfoo.so",
+                Some(DependOp::Eq),
+                Some("1-64"),
+                None,
+            ),
+            ("foo: for bar", "foo", None, None, Some("for bar")),
+            (
+                "foo>=1.0: for bar: baz",
+                "foo",
+                Some(DependOp::Ge),
+                Some("1.0"),
+                Some("for bar: baz"),
+            ),
+            // Epoch separator without a following space is not a description
+            ("foo=1:2.0", "foo", Some(DependOp::Eq), Some("1:2.0"), None),
+        ] {
+            let depend = Depend::parse(s);
+            assert_eq!(depend.name(), name, "{}", s);
+            assert_eq!(depend.op(), op, "{}", s);
+            assert_eq!(depend.version(), version, "{}", s);
+            assert_eq!(depend.description(), description, "{}", s);
+            assert_eq!(depend.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_matches_version() {
+        for &(s, version, expected) in &[
+            ("foo", "1.0", true),
+            ("foo=1.0", "1.0", true),
+            ("foo=1.0", "1.0-1", true),
+            ("foo=1.0-1", "1.0-2", false),
+            ("foo<1.0", "0.9", true),
+            ("foo<1.0", "1.0", false),
+            ("foo<=1.0", "1.0", true),
+            ("foo<=1.0", "1.1", false),
+            ("foo>1.0", "1.0", false),
+            ("foo>1.0", "1:0.1", true),
+            ("foo>=1.0", "1.0", true),
+            ("foo>=1.0", "1.0rc1", false),
+        ] {
+            assert_eq!(
+                Depend::parse(s).matches_version(version),
+                expected,
+                "{} with {}",
+                s,
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn test_satisfied_by() {
+        let provides = parse_all(&[
+            "bar".to_owned(),
+            "baz=2.0".to_owned(),
+            "qux>=1.0".to_owned(),
+        ]);
+        for &(s, expected) in &[
+            ("foo", true),
+            ("foo>=1.0", true),
+            ("foo>1.0-1", false),
+            ("bar", true),
+            ("bar>=1.0", false),
+            ("baz", true),
+            ("baz>=1.0", true),
+            ("baz=2.0", true),
+            ("baz<2.0", false),
+            ("qux", true),
+            ("qux>=1.0", false),
+            ("quux", false),
+        ] {
+            assert_eq!(
+                Depend::parse(s).satisfied_by("foo", "1.0-1", &provides),
+                expected,
+                "{}",
+                s
+            );
+        }
+    }
+}
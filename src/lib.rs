@@ -1,13 +1,61 @@
 pub mod omakase;
 
 mod builder;
+mod compression;
+mod create;
+mod depend;
+mod lint;
+mod lock;
 mod package;
 mod repository;
 mod signer;
+mod verify;
+mod version;
 
 pub use builder::Arch;
 pub use builder::Builder;
 pub use builder::ChrootHelper;
+pub use compression::Compression;
+pub use compression::UnknownFormatError;
+pub use create::create_package;
+pub use depend::Depend;
+pub use depend::DependOp;
+pub use lint::Finding;
+pub use lint::Linter;
+pub use lint::Rule;
+pub use lint::Severity;
+pub use package::BuildInfo;
+pub use package::ElfFile;
+pub use package::InstalledPackage;
+pub use package::MtreeEntry;
+pub use package::MtreeType;
 pub use package::Package;
+pub use package::PkgInfo;
+pub use repository::files_path;
+pub use repository::stale_package_files;
+pub use repository::AddMode;
+pub use repository::AddResult;
+pub use repository::Desc;
+pub use repository::FileQuery;
+pub use repository::PackageEntry;
 pub use repository::Repository;
+pub use repository::RepositoryPair;
+pub use signer::append_old_signatures;
+pub use signer::CommandSigner;
+pub use signer::GpgmeSigner;
+pub use signer::MultiSigner;
+pub use signer::OpenPgpSigner;
+pub use signer::SecretSource;
 pub use signer::Signer;
+pub use signer::SignerBackend;
+pub use signer::SignerConfig;
+pub use signer::Verifier;
+pub use signer::VerifyConfig;
+pub use verify::check_depends;
+pub use verify::check_sonames;
+pub use verify::compare_databases;
+pub use verify::verify_package_contents;
+pub use verify::verify_packages;
+pub use verify::Problem;
+pub use version::vercmp;
+pub use version::Version;
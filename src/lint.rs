@@ -0,0 +1,455 @@
+/// Built-in lint rules over a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Deserialize)]
+pub enum Rule {
+    #[serde(rename = "empty-license")]
+    EmptyLicense,
+    /// Files under /usr/local or /home
+    #[serde(rename = "forbidden-dir")]
+    ForbiddenDir,
+    #[serde(rename = "world-writable")]
+    WorldWritable,
+    #[serde(rename = "missing-pkgdesc")]
+    MissingPkgdesc,
+    #[serde(rename = "missing-url")]
+    MissingUrl,
+    /// Architecture in the filename differs from the one in .PKGINFO
+    #[serde(rename = "arch-mismatch")]
+    ArchMismatch,
+    /// Packages without any files except for directories
+    #[serde(rename = "empty-package")]
+    EmptyPackage,
+    /// Files outside the directories defined by FHS and Arch packaging standards
+    #[serde(rename = "non-fhs")]
+    NonFhs,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 8] = [
+        Rule::EmptyLicense,
+        Rule::ForbiddenDir,
+        Rule::WorldWritable,
+        Rule::MissingPkgdesc,
+        Rule::MissingUrl,
+        Rule::ArchMismatch,
+        Rule::EmptyPackage,
+        Rule::NonFhs,
+    ];
+
+    pub fn default_severity(self) -> Severity {
+        match self {
+            Rule::ForbiddenDir | Rule::WorldWritable | Rule::ArchMismatch => Severity::Error,
+            Rule::EmptyLicense
+            | Rule::MissingPkgdesc
+            | Rule::MissingUrl
+            | Rule::EmptyPackage
+            | Rule::NonFhs => Severity::Warning,
+        }
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Rule::EmptyLicense => "empty-license".fmt(f),
+            Rule::ForbiddenDir => "forbidden-dir".fmt(f),
+            Rule::WorldWritable => "world-writable".fmt(f),
+            Rule::MissingPkgdesc => "missing-pkgdesc".fmt(f),
+            Rule::MissingUrl => "missing-url".fmt(f),
+            Rule::ArchMismatch => "arch-mismatch".fmt(f),
+            Rule::EmptyPackage => "empty-package".fmt(f),
+            Rule::NonFhs => "non-fhs".fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rule::ALL
+            .iter()
+            .copied()
+            .find(|rule| rule.to_string() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown lint rule: {s}"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+pub enum Severity {
+    #[serde(rename = "ignore")]
+    Ignore,
+    #[serde(rename = "warning")]
+    Warning,
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Severity::Ignore => "ignore".fmt(f),
+            Severity::Warning => "warning".fmt(f),
+            Severity::Error => "error".fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            _ => anyhow::bail!("Unknown severity: {s}"),
+        }
+    }
+}
+
+/// Violation of a lint rule
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub pkgname: String,
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}: {}: {} [{}]",
+            self.severity, self.pkgname, self.message, self.rule
+        )
+    }
+}
+
+/// Runs the built-in rules with configurable severities
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    severities: std::collections::HashMap<Rule, Severity>,
+}
+
+// Top-level directories and directories under /usr packages may install files into
+const FHS_DIRS: [&str; 6] = ["boot", "etc", "opt", "srv", "usr", "var"];
+const FHS_USR_DIRS: [&str; 6] = ["bin", "include", "lib", "lib32", "share", "src"];
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter::default()
+    }
+
+    pub fn set_severity(&mut self, rule: Rule, severity: Severity) {
+        self.severities.insert(rule, severity);
+    }
+
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.severities
+            .get(&rule)
+            .copied()
+            .unwrap_or_else(|| rule.default_severity())
+    }
+
+    /// Run all rules not ignored against `package`
+    pub fn lint(&self, package: &super::package::Package) -> Vec<Finding> {
+        let mut findings = vec![];
+        let mut report = |rule: Rule, message: String| {
+            let severity = self.severity(rule);
+            if severity != Severity::Ignore {
+                findings.push(Finding {
+                    pkgname: package.pkgname().to_owned(),
+                    rule,
+                    severity,
+                    message,
+                });
+            }
+        };
+
+        if package.license().is_empty() {
+            report(Rule::EmptyLicense, "license is empty".to_owned());
+        }
+        if package.pkgdesc().is_empty() {
+            report(Rule::MissingPkgdesc, "pkgdesc is missing".to_owned());
+        }
+        if package.url().is_empty() {
+            report(Rule::MissingUrl, "url is missing".to_owned());
+        }
+        if let Some(arch) = filename_arch(package.filename()) {
+            if arch != package.arch() {
+                report(
+                    Rule::ArchMismatch,
+                    format!(
+                        "arch is {} in the filename but {} in .PKGINFO",
+                        arch,
+                        package.arch()
+                    ),
+                );
+            }
+        }
+
+        let mut has_files = false;
+        for path in package.files() {
+            let is_dir = path.as_os_str().to_string_lossy().ends_with('/');
+            has_files |= !is_dir;
+            let mut components = path.components().filter_map(|c| c.as_os_str().to_str());
+            let top = components.next().unwrap_or_default();
+            let second = components.next();
+            if (top == "usr" && second == Some("local")) || top == "home" {
+                if !is_dir || second.is_none() || top == "home" {
+                    report(
+                        Rule::ForbiddenDir,
+                        format!("{} is under /{}", path.display(), top),
+                    );
+                }
+            } else if !FHS_DIRS.contains(&top)
+                || (top == "usr" && second.is_some_and(|dir| !FHS_USR_DIRS.contains(&dir)))
+            {
+                report(
+                    Rule::NonFhs,
+                    format!("{} is outside the FHS", path.display()),
+                );
+            }
+        }
+        if !has_files {
+            report(Rule::EmptyPackage, "package has no files".to_owned());
+        }
+
+        for entry in package.mtree().into_iter().flatten() {
+            if let Some(mode) = entry.mode {
+                // Sticky directories like /tmp are fine
+                if mode & 0o002 != 0
+                    && mode & 0o1000 == 0
+                    && entry.file_type != super::package::MtreeType::Link
+                {
+                    report(
+                        Rule::WorldWritable,
+                        format!("{} is world-writable ({:o})", entry.path.display(), mode),
+                    );
+                }
+            }
+        }
+        findings
+    }
+
+    /// Log warnings of `package` and fail with the errors if any
+    pub fn check(&self, package: &super::package::Package) -> Result<(), anyhow::Error> {
+        let mut errors = vec![];
+        for finding in self.lint(package) {
+            if finding.severity == Severity::Error {
+                errors.push(finding.to_string());
+            } else {
+                tracing::warn!("{}", finding);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} lint errors found in {}:\n{}",
+                errors.len(),
+                package.filename().to_string_lossy(),
+                errors.join("\n")
+            ))
+        }
+    }
+}
+
+/// x86_64 of foo-1.0-1-x86_64.pkg.tar.zst
+fn filename_arch(filename: &std::ffi::OsStr) -> Option<&str> {
+    let filename = filename.to_str()?;
+    let stem = &filename[..filename.find(".pkg.tar")?];
+    stem.rsplit('-').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Linter, Rule, Severity};
+    use crate::package::Package;
+
+    const PKGINFO: &str = "pkgname = foo\npkgver = 1.0-1\npkgdesc = Foo\nurl = https://example.com\narch = x86_64\nlicense = MIT\n";
+
+    fn append(builder: &mut tar::Builder<Vec<u8>>, path: &str, body: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        if path.ends_with('/') {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+        }
+        header.set_size(body.len() as u64);
+        builder.append_data(&mut header, path, body).unwrap();
+    }
+
+    // Uncompressed package with members at `paths`, where paths ending with / are directories
+    fn package(filename: &str, pkginfo: &str, mtree: &str, paths: &[&str]) -> Package {
+        use std::io::Write as _;
+
+        let mut mtree_gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        mtree_gz.write_all(mtree.as_bytes()).unwrap();
+        let mut builder = tar::Builder::new(vec![]);
+        append(&mut builder, ".MTREE", &mtree_gz.finish().unwrap());
+        append(&mut builder, ".PKGINFO", pkginfo.as_bytes());
+        for path in paths {
+            append(&mut builder, path, b"");
+        }
+        let buf = builder.into_inner().unwrap();
+        Package::from_reader(buf.as_slice(), std::ffi::OsStr::new(filename)).unwrap()
+    }
+
+    fn lint(linter: &Linter, pkginfo: &str, mtree: &str, paths: &[&str]) -> Vec<(Rule, Severity)> {
+        let package = package("foo-1.0-1-x86_64.pkg.tar.zst", pkginfo, mtree, paths);
+        linter
+            .lint(&package)
+            .into_iter()
+            .map(|finding| (finding.rule, finding.severity))
+            .collect()
+    }
+
+    fn lint_paths(paths: &[&str]) -> Vec<(Rule, Severity)> {
+        lint(&Linter::new(), PKGINFO, "#mtree\n", paths)
+    }
+
+    #[test]
+    fn test_clean_package() {
+        assert_eq!(
+            lint_paths(&["usr/", "usr/bin/", "usr/bin/foo", "etc/foo.conf"]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        assert_eq!(
+            lint(
+                &Linter::new(),
+                "pkgname = foo\npkgver = 1.0-1\narch = x86_64\n",
+                "#mtree\n",
+                &["usr/bin/foo"]
+            ),
+            vec![
+                (Rule::EmptyLicense, Severity::Warning),
+                (Rule::MissingPkgdesc, Severity::Warning),
+                (Rule::MissingUrl, Severity::Warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arch_mismatch() {
+        let package = package(
+            "foo-1.0-1-any.pkg.tar.zst",
+            PKGINFO,
+            "#mtree\n",
+            &["usr/bin/foo"],
+        );
+        let findings = Linter::new().lint(&package);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, Rule::ArchMismatch);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(
+            findings[0].message,
+            "arch is any in the filename but x86_64 in .PKGINFO"
+        );
+    }
+
+    #[test]
+    fn test_empty_package() {
+        assert_eq!(
+            lint_paths(&["usr/", "usr/share/"]),
+            vec![(Rule::EmptyPackage, Severity::Warning)]
+        );
+    }
+
+    #[test]
+    fn test_forbidden_dir() {
+        // Directories such as /usr/local/ itself are created by filesystem anyway
+        assert_eq!(lint_paths(&["usr/local/", "usr/bin/foo"]), vec![]);
+        assert_eq!(
+            lint_paths(&["usr/local/", "usr/local/bin/", "usr/local/bin/foo"]),
+            vec![(Rule::ForbiddenDir, Severity::Error)]
+        );
+        assert_eq!(
+            lint_paths(&["home/", "usr/bin/foo"]),
+            vec![(Rule::ForbiddenDir, Severity::Error)]
+        );
+    }
+
+    #[test]
+    fn test_non_fhs() {
+        assert_eq!(
+            lint_paths(&[
+                "foo/bar",
+                "usr/lib64/libfoo.so",
+                "usr/share/foo",
+                "opt/foo/bar"
+            ]),
+            vec![
+                (Rule::NonFhs, Severity::Warning),
+                (Rule::NonFhs, Severity::Warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_world_writable() {
+        let mtree = "#mtree\n\
+                     /set type=file uid=0 gid=0 mode=644\n\
+                     ./usr/bin/foo mode=777\n\
+                     ./usr/bin/bar mode=755\n\
+                     ./usr/bin/baz type=link mode=777 link=foo\n\
+                     ./var/tmp type=dir mode=1777\n\
+                     ./var/spool type=dir mode=777\n";
+        let package = package(
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            PKGINFO,
+            mtree,
+            &[
+                "usr/bin/foo",
+                "usr/bin/bar",
+                "usr/bin/baz",
+                "var/tmp/",
+                "var/spool/",
+            ],
+        );
+        let messages: Vec<_> = Linter::new()
+            .lint(&package)
+            .into_iter()
+            .map(|finding| {
+                assert_eq!(finding.rule, Rule::WorldWritable);
+                finding.message
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "usr/bin/foo is world-writable (777)",
+                "var/spool is world-writable (777)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_severity() {
+        let mut linter = Linter::new();
+        linter.set_severity(Rule::MissingUrl, Severity::Error);
+        linter.set_severity(Rule::MissingPkgdesc, Severity::Ignore);
+        let pkginfo = "pkgname = foo\npkgver = 1.0-1\narch = x86_64\nlicense = MIT\n";
+        assert_eq!(
+            lint(&linter, pkginfo, "#mtree\n", &["usr/bin/foo"]),
+            vec![(Rule::MissingUrl, Severity::Error)]
+        );
+        let package = package(
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            pkginfo,
+            "#mtree\n",
+            &["usr/bin/foo"],
+        );
+        assert!(linter.check(&package).is_err());
+
+        linter.set_severity(Rule::MissingUrl, Severity::Warning);
+        assert!(linter.check(&package).is_ok());
+    }
+}
@@ -0,0 +1,84 @@
+/// Lock file compatible with repo-add, which creates foo.db.lck containing its PID while
+/// modifying foo.db and foo.files
+#[derive(Debug)]
+pub struct Lock {
+    path: std::path::PathBuf,
+}
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+impl Lock {
+    /// Create the lock file for the database at `db_path`, waiting up to `timeout` for another
+    /// process to release it
+    pub async fn acquire<P>(db_path: P, timeout: std::time::Duration) -> Result<Self, anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        use std::io::Write as _;
+
+        let path = lock_path(db_path.as_ref());
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    let lock = Lock { path };
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(lock);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let now = std::time::Instant::now();
+                    if now >= deadline {
+                        return Err(held_error(&path));
+                    }
+                    tokio::time::sleep(POLL_INTERVAL.min(deadline - now)).await;
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::from(e)
+                        .context(format!("Unable to create lock file {}", path.display())));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Unable to remove lock file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// foo.db and foo.files share foo.db.lck like repo-add
+fn lock_path(db_path: &std::path::Path) -> std::path::PathBuf {
+    let filename = db_path.file_name().unwrap_or_default().to_string_lossy();
+    match filename
+        .strip_suffix(".db")
+        .or_else(|| filename.strip_suffix(".files"))
+    {
+        Some(prefix) => db_path.with_file_name(format!("{}.db.lck", prefix)),
+        None => {
+            let mut path = db_path.as_os_str().to_os_string();
+            path.push(".lck");
+            std::path::PathBuf::from(path)
+        }
+    }
+}
+
+fn held_error(path: &std::path::Path) -> anyhow::Error {
+    match std::fs::read_to_string(path) {
+        Ok(pid) if !pid.trim().is_empty() => anyhow::anyhow!(
+            "Unable to acquire lock file {}: held by process {}",
+            path.display(),
+            pid.trim()
+        ),
+        _ => anyhow::anyhow!(
+            "Unable to acquire lock file {}: held by another process",
+            path.display()
+        ),
+    }
+}
@@ -20,6 +20,32 @@ enum Subcommand {
     FilesAdd(FilesAddArgs),
     /// Remove PACKAGE_NAME from FILES_PATH
     FilesRemove(FilesRemoveArgs),
+    /// List packages in DB_PATH
+    RepoList(RepoListArgs),
+    /// Show information of PACKAGE_NAME in DB_PATH
+    RepoInfo(RepoInfoArgs),
+    /// Search packages whose name or description matches PATTERN in DB_PATH
+    RepoSearch(RepoSearchArgs),
+    /// Search packages owning files in FILES_PATH
+    FilesSearch(FilesSearchArgs),
+    /// Verify packages in DB_PATH against files in the repository directory
+    RepoVerify(RepoVerifyArgs),
+    /// Report dependencies of packages in DB_PATH which cannot be resolved
+    RepoCheckDeps(RepoCheckDepsArgs),
+    /// List pkgbases in DB_PATH to be rebuilt after updating a package
+    RebuildList(RebuildListArgs),
+    /// Recreate databases from packages in REPO_DIR
+    RepoRebuild(RepoRebuildArgs),
+    /// Re-sign packages in DB_PATH and the databases with new keys
+    RepoResign(RepoResignArgs),
+    /// Show information of a package file
+    Inspect(InspectArgs),
+    /// Verify contents of a package file against its .MTREE
+    PackageVerify(PackageVerifyArgs),
+    /// Check a package file with lint rules and its soname provides and depends
+    PackageLint(PackageLintArgs),
+    /// Create a package file from files in STAGING_DIR without makepkg
+    PackageCreate(Box<PackageCreateArgs>),
     /// Manage repository with S3
     Omakase(OmakaseArgs),
 }
@@ -29,9 +55,9 @@ struct BuildArgs {
     /// Path to chroot top
     #[arg(long)]
     chroot_dir: std::path::PathBuf,
-    /// GPG key to sign packages
+    /// GPG key to sign packages. Signatures by all keys are combined when given multiple times.
     #[arg(long)]
-    package_key: Option<String>,
+    package_key: Vec<String>,
     /// Path to the directory to store sources
     #[arg(long)]
     srcdest: Option<std::path::PathBuf>,
@@ -41,46 +67,94 @@ struct BuildArgs {
     /// Path to the repository directory
     #[arg(long)]
     repo_dir: std::path::PathBuf,
-    /// GPG key to sign repository database
+    /// GPG key to sign repository database. Signatures by all keys are combined when given
+    /// multiple times.
     #[arg(long)]
-    repo_key: Option<String>,
+    repo_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    #[command(flatten)]
+    db_compression: DbCompressionArgs,
     /// Architecture
     #[arg(long)]
     arch: guzuta::Arch,
     /// Repository name
     #[arg(long)]
     repo_name: String,
+    #[command(flatten)]
+    add_mode: AddModeArgs,
+    #[command(flatten)]
+    remove_old: RemoveOldArgs,
+    #[command(flatten)]
+    lock: LockArgs,
+    #[command(flatten)]
+    lint: LintArgs,
     ///Path to the directory containing PKGBUILD
     package_dir: std::path::PathBuf,
 }
 
 #[derive(Debug, clap::Args)]
 struct RepoAddArgs {
-    /// GPG key to sign repository database
+    /// GPG key to sign repository database. Signatures by all keys are combined when given
+    /// multiple times.
     #[arg(long)]
-    repo_key: Option<String>,
+    repo_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    #[command(flatten)]
+    add_mode: AddModeArgs,
+    #[command(flatten)]
+    remove_old: RemoveOldArgs,
+    #[command(flatten)]
+    db_compression: DbCompressionArgs,
+    #[command(flatten)]
+    lock: LockArgs,
+    #[command(flatten)]
+    lint: LintArgs,
+    #[command(flatten)]
+    verify: VerifyArgs,
     /// Path to package to be added
     package_path: std::path::PathBuf,
-    /// Path to repository database
+    /// Path to repository database such as foo.db or foo.db.tar.gz. foo.files next to it is
+    /// updated as well
     db_path: std::path::PathBuf,
 }
 
 #[derive(Debug, clap::Args)]
 struct RepoRemoveArgs {
-    /// GPG key to sign repository database
+    /// GPG key to sign repository database. Signatures by all keys are combined when given
+    /// multiple times.
     #[arg(long)]
-    repo_key: Option<String>,
+    repo_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    #[command(flatten)]
+    remove_old: RemoveOldArgs,
+    #[command(flatten)]
+    db_compression: DbCompressionArgs,
+    #[command(flatten)]
+    lock: LockArgs,
     /// Package name to be removed
     package_name: String,
-    /// Path to repository database
+    /// Path to repository database such as foo.db or foo.db.tar.gz. foo.files next to it is
+    /// updated as well
     db_path: std::path::PathBuf,
 }
 
 #[derive(Debug, clap::Args)]
 struct FilesAddArgs {
-    /// GPG key to sign repository database
+    /// GPG key to sign repository database. Signatures by all keys are combined when given
+    /// multiple times.
     #[arg(long)]
-    repo_key: Option<String>,
+    repo_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    #[command(flatten)]
+    add_mode: AddModeArgs,
+    #[command(flatten)]
+    db_compression: DbCompressionArgs,
+    #[command(flatten)]
+    lock: LockArgs,
     /// Path to package to be added
     package_path: std::path::PathBuf,
     /// Path to repository database
@@ -89,15 +163,512 @@ struct FilesAddArgs {
 
 #[derive(Debug, clap::Args)]
 struct FilesRemoveArgs {
-    /// GPG key to sign repository database
+    /// GPG key to sign repository database. Signatures by all keys are combined when given
+    /// multiple times.
     #[arg(long)]
-    repo_key: Option<String>,
+    repo_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    #[command(flatten)]
+    db_compression: DbCompressionArgs,
+    #[command(flatten)]
+    lock: LockArgs,
     /// Package name to be removed
     package_name: String,
     /// Path to repository database
     files_path: std::path::PathBuf,
 }
 
+#[derive(Debug, clap::Args)]
+struct RepoListArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// Path to repository database
+    db_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RepoInfoArgs {
+    /// Package name to be shown
+    package_name: String,
+    /// Path to repository database
+    db_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RepoSearchArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// Regular expression matched against package names and descriptions, ignoring case
+    pattern: String,
+    /// Path to repository database
+    db_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct FilesSearchArgs {
+    /// Interpret QUERY as a regular expression matched against paths without the leading slash
+    #[arg(short = 'x', long)]
+    regex: bool,
+    /// List files of the package named QUERY
+    #[arg(short = 'l', long, conflicts_with = "regex")]
+    list: bool,
+    /// Path if it contains '/', file name otherwise
+    query: String,
+    /// Path to files database
+    files_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RepoVerifyArgs {
+    /// Path to files database to be compared with DB_PATH [default: DB_PATH with .files instead of .db]
+    #[arg(long)]
+    files_path: Option<std::path::PathBuf>,
+    /// Report packages without signature files
+    #[arg(long)]
+    require_signatures: bool,
+    /// Path to repository database
+    db_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RepoCheckDepsArgs {
+    /// Path to another database to resolve dependencies, such as a copy of core.db
+    #[arg(long)]
+    sync_db: Vec<std::path::PathBuf>,
+    /// Path to repository database
+    db_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RebuildListArgs {
+    /// Name of the updated package
+    package_name: String,
+    /// Path to repository database
+    db_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RepoRebuildArgs {
+    /// GPG key to sign repository database. Signatures by all keys are combined when given
+    /// multiple times.
+    #[arg(long)]
+    repo_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    #[command(flatten)]
+    db_compression: DbCompressionArgs,
+    #[command(flatten)]
+    lock: LockArgs,
+    /// Repository name
+    #[arg(long)]
+    repo_name: String,
+    /// Path to the repository directory
+    repo_dir: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RepoResignArgs {
+    /// GPG key to sign packages. Signatures by all keys are combined when given multiple times.
+    #[arg(long)]
+    package_key: Vec<String>,
+    /// GPG key to sign repository database. Signatures by all keys are combined when given
+    /// multiple times.
+    #[arg(long)]
+    repo_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    #[command(flatten)]
+    keep_old: KeepOldSignaturesArgs,
+    #[command(flatten)]
+    db_compression: DbCompressionArgs,
+    #[command(flatten)]
+    lock: LockArgs,
+    /// Path to repository database such as foo.db or foo.db.tar.gz. foo.files next to it is
+    /// updated as well
+    db_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct KeepOldSignaturesArgs {
+    /// Append the old signatures of packages to the new ones for a transition period
+    #[arg(long)]
+    keep_old_signatures: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct InspectArgs {
+    /// Show the build environment recorded in .BUILDINFO
+    #[arg(long)]
+    buildinfo: bool,
+    /// Path to package
+    package_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct PackageVerifyArgs {
+    /// Path to package
+    package_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct PackageLintArgs {
+    /// Path to database to resolve needed sonames, such as the target repository and copies of
+    /// core.files. Files databases also resolve sonames by the files they ship.
+    #[arg(long)]
+    db: Vec<std::path::PathBuf>,
+    #[command(flatten)]
+    lint_severity: LintSeverityArgs,
+    /// Path to package
+    package_path: std::path::PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct PackageCreateArgs {
+    /// Path to YAML describing the package with the fields of .PKGINFO such as pkgname, pkgver
+    /// (including pkgrel), arch and depends. Options below override the fields.
+    #[arg(long)]
+    pkginfo: Option<std::path::PathBuf>,
+    #[arg(long)]
+    pkgname: Option<String>,
+    #[arg(long)]
+    pkgbase: Option<String>,
+    /// Version including pkgrel, e.g. 1.0.0-1
+    #[arg(long)]
+    pkgver: Option<String>,
+    #[arg(long)]
+    pkgdesc: Option<String>,
+    #[arg(long)]
+    url: Option<String>,
+    #[arg(long)]
+    arch: Option<String>,
+    #[arg(long)]
+    packager: Option<String>,
+    /// Build date in seconds since the epoch [default: SOURCE_DATE_EPOCH or the current time]
+    #[arg(long)]
+    builddate: Option<u64>,
+    #[arg(long)]
+    license: Vec<String>,
+    #[arg(long)]
+    group: Vec<String>,
+    #[arg(long)]
+    depend: Vec<String>,
+    #[arg(long)]
+    optdepend: Vec<String>,
+    #[arg(long)]
+    makedepend: Vec<String>,
+    #[arg(long)]
+    checkdepend: Vec<String>,
+    #[arg(long)]
+    conflict: Vec<String>,
+    #[arg(long)]
+    provides: Vec<String>,
+    #[arg(long)]
+    replaces: Vec<String>,
+    #[arg(long)]
+    backup: Vec<String>,
+    /// GPG key to sign the package. Signatures by all keys are combined when given multiple
+    /// times.
+    #[arg(long)]
+    package_key: Vec<String>,
+    #[command(flatten)]
+    signer: SignerArgs,
+    /// Path to the directory to store the package
+    #[arg(long, default_value = ".")]
+    out_dir: std::path::PathBuf,
+    /// Path to the directory containing files to be installed, like $pkgdir of makepkg
+    staging_dir: std::path::PathBuf,
+}
+
+impl PackageCreateArgs {
+    fn pkginfo(&self) -> Result<guzuta::PkgInfo> {
+        let mut pkginfo = if let Some(ref path) = self.pkginfo {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("Unable to open {}", path.display()))?;
+            serde_yaml::from_reader(file)
+                .with_context(|| format!("Unable to load YAML from {}", path.display()))?
+        } else {
+            guzuta::PkgInfo::default()
+        };
+        for (field, val) in [
+            (&mut pkginfo.pkgname, &self.pkgname),
+            (&mut pkginfo.pkgbase, &self.pkgbase),
+            (&mut pkginfo.pkgver, &self.pkgver),
+            (&mut pkginfo.pkgdesc, &self.pkgdesc),
+            (&mut pkginfo.url, &self.url),
+            (&mut pkginfo.arch, &self.arch),
+            (&mut pkginfo.packager, &self.packager),
+        ] {
+            if let Some(val) = val {
+                field.clone_from(val);
+            }
+        }
+        for (field, vals) in [
+            (&mut pkginfo.license, &self.license),
+            (&mut pkginfo.groups, &self.group),
+            (&mut pkginfo.depends, &self.depend),
+            (&mut pkginfo.optdepends, &self.optdepend),
+            (&mut pkginfo.makedepends, &self.makedepend),
+            (&mut pkginfo.checkdepends, &self.checkdepend),
+            (&mut pkginfo.conflicts, &self.conflict),
+            (&mut pkginfo.provides, &self.provides),
+            (&mut pkginfo.replaces, &self.replaces),
+            (&mut pkginfo.backups, &self.backup),
+        ] {
+            if !vals.is_empty() {
+                field.clone_from(vals);
+            }
+        }
+        if let Some(builddate) = self.builddate {
+            pkginfo.builddate = builddate;
+        } else if pkginfo.builddate == 0 {
+            pkginfo.builddate = match std::env::var("SOURCE_DATE_EPOCH") {
+                Ok(epoch) => epoch
+                    .parse()
+                    .with_context(|| format!("Invalid SOURCE_DATE_EPOCH: {}", epoch))?,
+                Err(_) => std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)?
+                    .as_secs(),
+            };
+        }
+        Ok(pkginfo)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct FilterArgs {
+    /// Only packages in the group
+    #[arg(long)]
+    group: Option<String>,
+    /// Only packages providing the name
+    #[arg(long)]
+    provides: Option<String>,
+    /// Only packages built by the packager
+    #[arg(long)]
+    packager: Option<String>,
+}
+
+impl FilterArgs {
+    /// Narrow down `entries` with the filters and sort them by name
+    fn apply<'r>(
+        &self,
+        repository: &'r guzuta::Repository,
+        mut entries: Vec<&'r guzuta::PackageEntry>,
+    ) -> Vec<&'r guzuta::PackageEntry> {
+        let mut retain = |matched: Vec<&guzuta::PackageEntry>| {
+            let names: std::collections::HashSet<_> =
+                matched.into_iter().map(|e| e.desc.pkgname()).collect();
+            entries.retain(|e| names.contains(e.desc.pkgname()));
+        };
+        if let Some(ref group) = self.group {
+            retain(repository.by_group(group).collect());
+        }
+        if let Some(ref provides) = self.provides {
+            retain(repository.by_provides(provides).collect());
+        }
+        if let Some(ref packager) = self.packager {
+            retain(repository.by_packager(packager).collect());
+        }
+        entries.sort_by(|a, b| a.desc.pkgname().cmp(b.desc.pkgname()));
+        entries
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct DbCompressionArgs {
+    /// Compression of repository database (none, gz, xz or zst). Defaults to the extension of
+    /// the database path like foo.db.tar.zst, or gz
+    #[arg(long = "db-compression", value_parser = parse_db_compression)]
+    compression: Option<guzuta::Compression>,
+    /// Compression level of repository database
+    #[arg(long = "db-compression-level")]
+    level: Option<u32>,
+}
+
+impl DbCompressionArgs {
+    fn compression_or(&self, default: guzuta::Compression) -> guzuta::Compression {
+        self.compression.unwrap_or(default)
+    }
+}
+
+fn parse_db_compression(s: &str) -> Result<guzuta::Compression> {
+    let compression: guzuta::Compression = s.parse()?;
+    if !compression.is_writable() {
+        anyhow::bail!("Unsupported compression for writing: {s}");
+    }
+    Ok(compression)
+}
+
+#[derive(Debug, clap::Args)]
+struct AddModeArgs {
+    /// Add packages even if they are older than the ones in the repository
+    #[arg(long)]
+    force: bool,
+    /// Only add packages that are newer than the ones in the repository
+    #[arg(short = 'n', long = "new", conflicts_with = "force")]
+    newer_only: bool,
+}
+
+impl AddModeArgs {
+    fn mode(&self) -> guzuta::AddMode {
+        if self.force {
+            guzuta::AddMode::Force
+        } else if self.newer_only {
+            guzuta::AddMode::NewerOnly
+        } else {
+            guzuta::AddMode::Default
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct LockArgs {
+    /// Seconds to wait for the lock of repository database held by another process
+    #[arg(long, default_value_t = 60)]
+    lock_timeout: u64,
+}
+
+impl LockArgs {
+    fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lock_timeout)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct SignerArgs {
+    /// Backend to sign with the keys (gpgme, openpgp or command). Keys are paths to secret key
+    /// files with openpgp.
+    #[arg(long = "signer", default_value = "gpgme")]
+    backend: guzuta::SignerBackend,
+    /// Shell command of the command backend, which reads a file from stdin and writes its
+    /// signature to stdout. The key is given in GUZUTA_SIGN_KEY.
+    #[arg(long, required_if_eq("backend", "command"))]
+    sign_command: Option<String>,
+    /// Import an armored secret key from file:PATH, env:NAME or command:COMMAND into a temporary
+    /// GNUPGHOME instead of using the user's keyring (gpgme only)
+    #[arg(long)]
+    secret_key: Option<guzuta::SecretSource>,
+    /// Read the passphrase of the secret key from file:PATH, env:NAME or command:COMMAND instead
+    /// of asking it with pinentry
+    #[arg(long)]
+    passphrase: Option<guzuta::SecretSource>,
+}
+
+impl SignerArgs {
+    fn signer(&self, keys: &[String]) -> Result<Option<Box<dyn guzuta::Signer>>> {
+        let config = guzuta::SignerConfig {
+            backend: self.backend,
+            command: self.sign_command.clone(),
+            secret_key: self.secret_key.clone(),
+            passphrase: self.passphrase.clone(),
+        };
+        config.combined_signer(keys)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct VerifyArgs {
+    /// Add packages without verifying their signatures
+    #[arg(long)]
+    skip_verify: bool,
+    /// Fingerprint of a key expected to sign packages. Packages without signatures are refused
+    /// when given.
+    #[arg(long = "verify-fingerprint")]
+    fingerprints: Vec<String>,
+    /// Path to public keys to verify signatures with instead of the user's keyring
+    #[arg(long)]
+    verify_keyring: Option<std::path::PathBuf>,
+}
+
+impl VerifyArgs {
+    fn verifier(&self) -> Result<Option<guzuta::Verifier>> {
+        if self.skip_verify {
+            return Ok(None);
+        }
+        let config = guzuta::VerifyConfig {
+            fingerprints: self.fingerprints.clone(),
+            keyring: self.verify_keyring.clone(),
+        };
+        Ok(Some(config.verifier()?))
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct LintArgs {
+    /// Lint packages before adding them to the repository and refuse them on errors
+    #[arg(long)]
+    lint: bool,
+    #[command(flatten)]
+    severity: LintSeverityArgs,
+}
+
+impl LintArgs {
+    fn linter(&self) -> Option<guzuta::Linter> {
+        if self.lint {
+            Some(self.severity.linter())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct LintSeverityArgs {
+    /// Override the severity of a lint rule in the form of RULE=SEVERITY, where SEVERITY is
+    /// ignore, warning or error
+    #[arg(long = "lint-severity", value_parser = parse_lint_severity)]
+    severities: Vec<(guzuta::Rule, guzuta::Severity)>,
+}
+
+impl LintSeverityArgs {
+    fn linter(&self) -> guzuta::Linter {
+        let mut linter = guzuta::Linter::new();
+        for &(rule, severity) in &self.severities {
+            linter.set_severity(rule, severity);
+        }
+        linter
+    }
+}
+
+fn parse_lint_severity(s: &str) -> Result<(guzuta::Rule, guzuta::Severity)> {
+    let (rule, severity) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("RULE=SEVERITY is expected: {s}"))?;
+    Ok((rule.parse()?, severity.parse()?))
+}
+
+#[derive(Debug, clap::Args)]
+struct RemoveOldArgs {
+    /// Remove package files and signatures no longer in the database
+    #[arg(short = 'R', long)]
+    remove_old: bool,
+    /// Number of superseded versions to retain when removing old packages
+    #[arg(long, default_value_t = 0, requires = "remove_old")]
+    keep: usize,
+}
+
+impl RemoveOldArgs {
+    /// Select package files of `pkgname` to be deleted. Besides the `displaced` one, other
+    /// versions in `existing` are considered when retaining versions.
+    fn select(
+        &self,
+        pkgname: &str,
+        displaced: Option<std::ffi::OsString>,
+        current: Option<&std::ffi::OsStr>,
+        existing: &[std::ffi::OsString],
+    ) -> Vec<std::ffi::OsString> {
+        let mut candidates: Vec<_> = displaced.into_iter().collect();
+        if self.keep > 0 {
+            for filename in existing {
+                if Some(filename.as_os_str()) != current && !candidates.contains(filename) {
+                    candidates.push(filename.to_owned());
+                }
+            }
+        }
+        guzuta::stale_package_files(&candidates, pkgname, self.keep)
+    }
+}
+
 #[derive(Debug, clap::Args)]
 struct OmakaseArgs {
     #[command(subcommand)]
@@ -110,18 +681,47 @@ enum OmakaseCommand {
     Build(OmakaseBuildArgs),
     /// Remove PACKAGE_NAME
     Remove(OmakaseRemoveArgs),
+    /// Re-sign all packages and the databases with the keys in .guzuta.yml
+    Resign(OmakaseResignArgs),
 }
 
 #[derive(Debug, clap::Args)]
 struct OmakaseBuildArgs {
+    #[command(flatten)]
+    add_mode: AddModeArgs,
+    #[command(flatten)]
+    remove_old: RemoveOldArgs,
+    #[command(flatten)]
+    lock: LockArgs,
+    /// Use the databases downloaded from S3 without verifying their signatures
+    #[arg(long)]
+    skip_verify: bool,
     package_name: String,
 }
 
 #[derive(Debug, clap::Args)]
 struct OmakaseRemoveArgs {
+    #[command(flatten)]
+    remove_old: RemoveOldArgs,
+    #[command(flatten)]
+    lock: LockArgs,
+    /// Use the databases downloaded from S3 without verifying their signatures
+    #[arg(long)]
+    skip_verify: bool,
     package_name: String,
 }
 
+#[derive(Debug, clap::Args)]
+struct OmakaseResignArgs {
+    #[command(flatten)]
+    keep_old: KeepOldSignaturesArgs,
+    #[command(flatten)]
+    lock: LockArgs,
+    /// Use the databases downloaded from S3 without verifying their signatures
+    #[arg(long)]
+    skip_verify: bool,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -135,18 +735,27 @@ async fn main() -> Result<()> {
 async fn run_subcommand(subcommand: Subcommand) -> Result<()> {
     match subcommand {
         Subcommand::Build(args) => build(args).await?,
-        Subcommand::RepoAdd(args) => {
-            repo_add(args).await;
-        }
-        Subcommand::RepoRemove(args) => {
-            repo_remove(args).await;
-        }
+        Subcommand::RepoAdd(args) => repo_add(args).await?,
+        Subcommand::RepoRemove(args) => repo_remove(args).await?,
         Subcommand::FilesAdd(args) => {
             files_add(args).await;
         }
         Subcommand::FilesRemove(args) => {
             files_remove(args).await;
         }
+        Subcommand::RepoList(args) => repo_list(args)?,
+        Subcommand::RepoInfo(args) => repo_info(args)?,
+        Subcommand::RepoSearch(args) => repo_search(args)?,
+        Subcommand::FilesSearch(args) => files_search(args)?,
+        Subcommand::RepoVerify(args) => repo_verify(args)?,
+        Subcommand::RepoCheckDeps(args) => repo_check_deps(args)?,
+        Subcommand::RebuildList(args) => rebuild_list(args)?,
+        Subcommand::RepoRebuild(args) => repo_rebuild(args).await?,
+        Subcommand::RepoResign(args) => repo_resign(args).await?,
+        Subcommand::Inspect(args) => inspect(args)?,
+        Subcommand::PackageVerify(args) => package_verify(args)?,
+        Subcommand::PackageLint(args) => package_lint(args)?,
+        Subcommand::PackageCreate(args) => package_create(*args).await?,
         Subcommand::Omakase(omakase_args) => match omakase_args.command {
             OmakaseCommand::Build(args) => {
                 omakase_build(args).await;
@@ -154,6 +763,9 @@ async fn run_subcommand(subcommand: Subcommand) -> Result<()> {
             OmakaseCommand::Remove(args) => {
                 omakase_remove(args).await;
             }
+            OmakaseCommand::Resign(args) => {
+                omakase_resign(args).await;
+            }
         },
     };
 
@@ -162,7 +774,7 @@ async fn run_subcommand(subcommand: Subcommand) -> Result<()> {
 
 async fn build(args: BuildArgs) -> Result<()> {
     let chroot = guzuta::ChrootHelper::new(&args.chroot_dir, args.arch);
-    let package_signer = args.package_key.as_deref().map(guzuta::Signer::new);
+    let package_signer = args.signer.signer(&args.package_key)?;
     let srcdest = args
         .srcdest
         .as_deref()
@@ -171,112 +783,241 @@ async fn build(args: BuildArgs) -> Result<()> {
         .logdest
         .as_deref()
         .unwrap_or_else(|| std::path::Path::new("."));
-    let builder = guzuta::Builder::new(package_signer, srcdest, logdest);
+    let mut builder = guzuta::Builder::new(package_signer.as_deref(), srcdest, logdest);
+    if let Some(linter) = args.lint.linter() {
+        builder.set_linter(linter);
+    }
 
-    let repo_signer = args.repo_key.as_deref().map(guzuta::Signer::new);
+    let repo_signer = args.signer.signer(&args.repo_key)?;
     let mut db_path = args.repo_dir.join(&args.repo_name).into_os_string();
     db_path.push(".db");
-    let mut files_path = args.repo_dir.join(&args.repo_name).into_os_string();
-    files_path.push(".files");
-    let mut db_repo = guzuta::Repository::new(std::path::PathBuf::from(db_path), repo_signer);
-    let mut files_repo = guzuta::Repository::new(std::path::PathBuf::from(files_path), repo_signer);
-    db_repo.load().with_context(|| {
-        format!(
-            "Unable to load database repository from {}",
-            db_repo.path().display()
-        )
-    })?;
-    files_repo.load().with_context(|| {
-        format!(
-            "Unable to load files repository from {}",
-            files_repo.path().display()
-        )
-    })?;
+    let mut repository = guzuta::RepositoryPair::from_db_path(
+        std::path::PathBuf::from(db_path),
+        repo_signer.as_deref(),
+    )?;
+    repository.set_compression(
+        args.db_compression.compression_or(repository.compression()),
+        args.db_compression.level,
+    );
 
     let package_dir = &args.package_dir;
     let package_paths = builder
-        .build_package(package_dir, args.repo_dir, chroot)
+        .build_package(package_dir, &args.repo_dir, chroot)
         .await
         .with_context(|| format!("Unable to build package in {}", package_dir.display()))?;
 
-    for path in package_paths {
-        let package = guzuta::Package::load(&path)
-            .unwrap_or_else(|_| panic!("Unable to load built package at {}", path.display()));
-        db_repo.add(&package);
-        files_repo.add(&package);
+    repository.lock(args.lock.timeout()).await?;
+    // Built packages are already linted by the builder
+    let displaced = add_packages(
+        &mut repository,
+        &package_paths,
+        args.add_mode.mode(),
+        None,
+        None,
+    )
+    .await?;
+
+    if args.remove_old.remove_old {
+        let existing = list_dir(&args.repo_dir)?;
+        for (package, filename) in displaced {
+            let old_files = args.remove_old.select(
+                package.pkgname(),
+                filename,
+                Some(package.filename()),
+                &existing,
+            );
+            remove_package_files(&args.repo_dir, &old_files)?;
+        }
     }
 
-    db_repo.save(false).await.unwrap_or_else(|_| {
-        panic!(
-            "Unable to save database repository to {}",
-            db_repo.path().display()
-        )
-    });
-    files_repo.save(true).await.unwrap_or_else(|_| {
-        panic!(
-            "Unable to save files repository to {}",
-            files_repo.path().display()
-        )
-    });
+    Ok(())
+}
+
+fn db_dir(db_path: &std::path::Path) -> &std::path::Path {
+    match db_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    }
+}
 
+fn list_dir<P>(dir: P) -> Result<Vec<std::ffi::OsString>>
+where
+    P: AsRef<std::path::Path>,
+{
+    let dir = dir.as_ref();
+    let mut filenames = vec![];
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Unable to read directory {}", dir.display()))?
+    {
+        filenames.push(entry?.file_name());
+    }
+    Ok(filenames)
+}
+
+fn remove_package_files<P>(dir: P, filenames: &[std::ffi::OsString]) -> Result<()>
+where
+    P: AsRef<std::path::Path>,
+{
+    for filename in filenames {
+        let path = dir.as_ref().join(filename);
+        let mut sig_path = path.clone().into_os_string();
+        sig_path.push(".sig");
+        for path in [path, std::path::PathBuf::from(sig_path)] {
+            match std::fs::remove_file(&path) {
+                Ok(_) => println!("Remove {}", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Unable to remove {}", path.display()))
+                }
+            }
+        }
+    }
     Ok(())
 }
 
-async fn repo_add(args: RepoAddArgs) {
-    let signer = args.repo_key.as_deref().map(guzuta::Signer::new);
-    let package_path = args.package_path;
-    let package = guzuta::Package::load(&package_path)
-        .unwrap_or_else(|_| panic!("Unable to load package {}", package_path.display()));
-    let mut repository = guzuta::Repository::new(args.db_path, signer);
+/// Load `repository`, add packages at `package_paths` to it and save it. Returns the added
+/// packages along with the package filenames of the replaced entries. With `linter` or
+/// `verifier`, nothing is saved if any package has lint errors or a bad signature.
+async fn add_packages<P>(
+    repository: &mut guzuta::RepositoryPair<'_>,
+    package_paths: &[P],
+    mode: guzuta::AddMode,
+    linter: Option<&guzuta::Linter>,
+    verifier: Option<&guzuta::Verifier>,
+) -> Result<Vec<(guzuta::Package, Option<std::ffi::OsString>)>>
+where
+    P: AsRef<std::path::Path>,
+{
+    repository.load()?;
+    let mut displaced = vec![];
+    for path in package_paths {
+        let path = path.as_ref();
+        if let Some(verifier) = verifier {
+            verify_package_signature(verifier, path)?;
+        }
+        let package = guzuta::Package::load(path)
+            .with_context(|| format!("Unable to load package {}", path.display()))?;
+        if let Some(linter) = linter {
+            linter.check(&package)?;
+        }
+        let result = repository
+            .add(&package, mode)
+            .with_context(|| format!("Unable to add package {}", path.display()))?;
+        if let guzuta::AddResult::Added(filename) = result {
+            displaced.push((package, filename));
+        }
+    }
+    repository.save().await?;
+    Ok(displaced)
+}
 
-    repository.load().unwrap_or_else(|_| {
-        panic!(
-            "Unable to load database repository from {}",
-            repository.path().display()
-        )
-    });
-    repository.add(&package);
-    repository.save(false).await.unwrap_or_else(|_| {
-        panic!(
-            "Unable to save database repository to {}",
-            repository.path().display()
-        )
-    });
+/// Verify `{path}.sig` if exists. It is required when the verifier expects specific keys.
+fn verify_package_signature(verifier: &guzuta::Verifier, path: &std::path::Path) -> Result<()> {
+    let mut sig_path = path.as_os_str().to_os_string();
+    sig_path.push(".sig");
+    let sig_path = std::path::PathBuf::from(sig_path);
+    if sig_path.exists() {
+        verifier
+            .verify(path, &sig_path)
+            .with_context(|| format!("Unable to verify signature of {}", path.display()))
+    } else if verifier.has_fingerprints() {
+        Err(anyhow::anyhow!(
+            "Signature {} not found",
+            sig_path.display()
+        ))
+    } else {
+        Ok(())
+    }
 }
 
-async fn repo_remove(args: RepoRemoveArgs) {
-    let signer = args.repo_key.as_deref().map(guzuta::Signer::new);
-    let mut repository = guzuta::Repository::new(args.db_path, signer);
+async fn repo_add(args: RepoAddArgs) -> Result<()> {
+    let verifier = args.verify.verifier()?;
+    let signer = args.signer.signer(&args.repo_key)?;
+    let mut repository =
+        guzuta::RepositoryPair::from_db_path(args.db_path.clone(), signer.as_deref())?;
+    repository.set_compression(
+        args.db_compression.compression_or(repository.compression()),
+        args.db_compression.level,
+    );
 
-    repository.load().unwrap_or_else(|_| {
-        panic!(
-            "Unable to load database repository from {}",
-            repository.path().display()
-        )
-    });
-    repository.remove(&args.package_name);
-    repository.save(false).await.unwrap_or_else(|_| {
-        panic!(
-            "Unable to save database repository to {}",
-            repository.path().display()
-        )
-    });
+    repository.lock(args.lock.timeout()).await?;
+    let displaced = add_packages(
+        &mut repository,
+        std::slice::from_ref(&args.package_path),
+        args.add_mode.mode(),
+        args.lint.linter().as_ref(),
+        verifier.as_ref(),
+    )
+    .await?;
+
+    if args.remove_old.remove_old {
+        let repo_dir = db_dir(&args.db_path);
+        let existing = list_dir(repo_dir)?;
+        for (package, filename) in displaced {
+            let old_files = args.remove_old.select(
+                package.pkgname(),
+                filename,
+                Some(package.filename()),
+                &existing,
+            );
+            remove_package_files(repo_dir, &old_files)?;
+        }
+    }
+    Ok(())
+}
+
+async fn repo_remove(args: RepoRemoveArgs) -> Result<()> {
+    let signer = args.signer.signer(&args.repo_key)?;
+    let mut repository =
+        guzuta::RepositoryPair::from_db_path(args.db_path.clone(), signer.as_deref())?;
+    repository.set_compression(
+        args.db_compression.compression_or(repository.compression()),
+        args.db_compression.level,
+    );
+
+    repository.lock(args.lock.timeout()).await?;
+    repository.load()?;
+    let filename = repository.remove(&args.package_name);
+    repository.save().await?;
+
+    if args.remove_old.remove_old {
+        let repo_dir = db_dir(&args.db_path);
+        let existing = list_dir(repo_dir)?;
+        let old_files = args
+            .remove_old
+            .select(&args.package_name, filename, None, &existing);
+        remove_package_files(repo_dir, &old_files)?;
+    }
+    Ok(())
 }
 
 async fn files_add(args: FilesAddArgs) {
-    let signer = args.repo_key.as_deref().map(guzuta::Signer::new);
+    let signer = args
+        .signer
+        .signer(&args.repo_key)
+        .unwrap_or_else(|e| panic!("{:#}", e));
     let package_path = args.package_path;
     let package = guzuta::Package::load(&package_path)
         .unwrap_or_else(|_| panic!("Unable to load package {}", package_path.display()));
-    let mut repository = guzuta::Repository::new(args.files_path, signer);
+    let mut repository = guzuta::Repository::new(args.files_path, signer.as_deref());
+    repository.set_compression(
+        args.db_compression.compression_or(repository.compression()),
+        args.db_compression.level,
+    );
 
+    repository
+        .lock(args.lock.timeout())
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
     repository.load().unwrap_or_else(|_| {
         panic!(
             "Unable to load files repository from {}",
             repository.path().display()
         )
     });
-    repository.add(&package);
+    repository
+        .add(&package, args.add_mode.mode())
+        .unwrap_or_else(|e| panic!("Unable to add package {}: {}", package_path.display(), e));
     repository.save(true).await.unwrap_or_else(|_| {
         panic!(
             "Unable to save files repository to {}",
@@ -286,9 +1027,20 @@ async fn files_add(args: FilesAddArgs) {
 }
 
 async fn files_remove(args: FilesRemoveArgs) {
-    let signer = args.repo_key.as_deref().map(guzuta::Signer::new);
-    let mut repository = guzuta::Repository::new(args.files_path, signer);
+    let signer = args
+        .signer
+        .signer(&args.repo_key)
+        .unwrap_or_else(|e| panic!("{:#}", e));
+    let mut repository = guzuta::Repository::new(args.files_path, signer.as_deref());
+    repository.set_compression(
+        args.db_compression.compression_or(repository.compression()),
+        args.db_compression.level,
+    );
 
+    repository
+        .lock(args.lock.timeout())
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
     repository.load().unwrap_or_else(|_| {
         panic!(
             "Unable to load files repository from {}",
@@ -304,13 +1056,549 @@ async fn files_remove(args: FilesRemoveArgs) {
     });
 }
 
+fn load_repository(db_path: std::path::PathBuf) -> Result<guzuta::Repository<'static>> {
+    let mut repository = guzuta::Repository::new(db_path, None);
+    repository.load().with_context(|| {
+        format!(
+            "Unable to load database repository from {}",
+            repository.path().display()
+        )
+    })?;
+    Ok(repository)
+}
+
+fn repo_list(args: RepoListArgs) -> Result<()> {
+    let repository = load_repository(args.db_path)?;
+    for entry in args
+        .filter
+        .apply(&repository, repository.entries().collect())
+    {
+        println!("{} {}", entry.desc.pkgname(), entry.desc.pkgver());
+    }
+    Ok(())
+}
+
+fn repo_info(args: RepoInfoArgs) -> Result<()> {
+    let package_name = args.package_name;
+    let repository = load_repository(args.db_path)?;
+    let desc = &repository
+        .get(&package_name)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Package {} is not found in {}",
+                package_name,
+                repository.path().display()
+            )
+        })?
+        .desc;
+    let list = |xs: &[String]| {
+        if xs.is_empty() {
+            "None".to_owned()
+        } else {
+            xs.join("  ")
+        }
+    };
+    println!("Name            : {}", desc.pkgname());
+    println!("Base            : {}", desc.pkgbase());
+    println!("Version         : {}", desc.pkgver());
+    println!("Description     : {}", desc.pkgdesc());
+    println!("Architecture    : {}", desc.arch());
+    println!("URL             : {}", desc.url());
+    println!("Licenses        : {}", list(desc.license()));
+    println!("Groups          : {}", list(desc.groups()));
+    println!("Provides        : {}", list(desc.provides()));
+    println!("Depends On      : {}", list(desc.depends()));
+    println!("Optional Deps   : {}", list(desc.optdepends()));
+    println!("Make Deps       : {}", list(desc.makedepends()));
+    println!("Check Deps      : {}", list(desc.checkdepends()));
+    println!("Conflicts With  : {}", list(desc.conflicts()));
+    println!("Replaces        : {}", list(desc.replaces()));
+    println!("Download Size   : {}", desc.csize());
+    println!("Installed Size  : {}", desc.isize());
+    println!("Packager        : {}", desc.packager());
+    println!("Build Date      : {}", desc.builddate());
+    println!("Filename        : {}", desc.filename().to_string_lossy());
+    println!("MD5 Sum         : {}", desc.md5sum());
+    println!("SHA-256 Sum     : {}", desc.sha256sum());
+    println!(
+        "Signatures      : {}",
+        if desc.pgpsig().is_empty() {
+            "None"
+        } else {
+            "Yes"
+        }
+    );
+    Ok(())
+}
+
+fn inspect(args: InspectArgs) -> Result<()> {
+    let package = guzuta::Package::load(&args.package_path)
+        .with_context(|| format!("Unable to load package {}", args.package_path.display()))?;
+    let list = |xs: &[String]| {
+        if xs.is_empty() {
+            "None".to_owned()
+        } else {
+            xs.join("  ")
+        }
+    };
+    if args.buildinfo {
+        let buildinfo = package.buildinfo().ok_or_else(|| {
+            anyhow::anyhow!(".BUILDINFO is not found in {}", args.package_path.display())
+        })?;
+        println!("Format          : {}", buildinfo.format);
+        println!("Name            : {}", buildinfo.pkgname);
+        println!("Base            : {}", buildinfo.pkgbase);
+        println!("Version         : {}", buildinfo.pkgver);
+        println!("Architecture    : {}", buildinfo.pkgarch);
+        println!("PKGBUILD SHA-256: {}", buildinfo.pkgbuild_sha256sum);
+        println!("Packager        : {}", buildinfo.packager);
+        println!("Build Date      : {}", buildinfo.builddate);
+        println!("Build Directory : {}", buildinfo.builddir);
+        println!("Start Directory : {}", buildinfo.startdir);
+        println!(
+            "Build Tool      : {} {}",
+            buildinfo.buildtool, buildinfo.buildtoolver
+        );
+        println!("Build Env       : {}", list(&buildinfo.buildenv));
+        println!("Options         : {}", list(&buildinfo.options));
+        println!("Installed       :");
+        for installed in &buildinfo.installed {
+            println!("    {}", installed);
+        }
+    } else {
+        println!("Name            : {}", package.pkgname());
+        println!("Base            : {}", package.pkgbase());
+        println!("Version         : {}", package.pkgver());
+        println!("Description     : {}", package.pkgdesc());
+        println!("Architecture    : {}", package.arch());
+        println!("URL             : {}", package.url());
+        println!("Licenses        : {}", list(package.license()));
+        println!("Groups          : {}", list(package.groups()));
+        println!("Provides        : {}", list(package.provides()));
+        println!("Depends On      : {}", list(package.depends()));
+        println!("Optional Deps   : {}", list(package.optdepends()));
+        println!("Make Deps       : {}", list(package.makedepends()));
+        println!("Check Deps      : {}", list(package.checkdepends()));
+        println!("Conflicts With  : {}", list(package.conflicts()));
+        println!("Replaces        : {}", list(package.replaces()));
+        println!("Backup Files    : {}", list(package.backups()));
+        println!("Compressed Size : {}", package.csize());
+        println!("Installed Size  : {}", package.isize());
+        println!("Packager        : {}", package.packager());
+        println!("Build Date      : {}", package.builddate());
+        println!("MD5 Sum         : {}", package.md5sum());
+        println!("SHA-256 Sum     : {}", package.sha256sum());
+        println!(
+            "Signatures      : {}",
+            if package.pgpsig().is_empty() {
+                "None"
+            } else {
+                "Yes"
+            }
+        );
+    }
+    Ok(())
+}
+
+fn repo_search(args: RepoSearchArgs) -> Result<()> {
+    let pattern = regex::RegexBuilder::new(&args.pattern)
+        .case_insensitive(true)
+        .build()
+        .with_context(|| format!("Invalid pattern {}", args.pattern))?;
+    let repository = load_repository(args.db_path)?;
+    for entry in args
+        .filter
+        .apply(&repository, repository.search(&pattern).collect())
+    {
+        println!("{} {}", entry.desc.pkgname(), entry.desc.pkgver());
+        println!("    {}", entry.desc.pkgdesc());
+    }
+    Ok(())
+}
+
+fn files_search(args: FilesSearchArgs) -> Result<()> {
+    let query_str = args.query;
+    let repository = load_repository(args.files_path)?;
+    if args.list {
+        let entry = repository.get(&query_str).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Package {} is not found in {}",
+                query_str,
+                repository.path().display()
+            )
+        })?;
+        for file in &entry.files {
+            println!("{} {}", entry.desc.pkgname(), file.display());
+        }
+        return Ok(());
+    }
+
+    let pattern;
+    let query = if args.regex {
+        pattern = regex::Regex::new(&query_str)
+            .with_context(|| format!("Invalid pattern {}", query_str))?;
+        guzuta::FileQuery::Regex(&pattern)
+    } else if query_str.contains('/') {
+        guzuta::FileQuery::Path(std::path::Path::new(&query_str))
+    } else {
+        guzuta::FileQuery::Basename(&query_str)
+    };
+    let mut matches = repository.search_files(query);
+    if matches.is_empty() {
+        anyhow::bail!(
+            "No files matching {} in {}",
+            query_str,
+            repository.path().display()
+        );
+    }
+    matches.sort_by(|(a, _), (b, _)| a.desc.pkgname().cmp(b.desc.pkgname()));
+    let mut last_pkgname = None;
+    for (entry, file) in matches {
+        if last_pkgname != Some(entry.desc.pkgname()) {
+            println!("{} {}", entry.desc.pkgname(), entry.desc.pkgver());
+            last_pkgname = Some(entry.desc.pkgname());
+        }
+        println!("    {}", file.display());
+    }
+    Ok(())
+}
+
+fn repo_verify(args: RepoVerifyArgs) -> Result<()> {
+    if !args.db_path.exists() {
+        anyhow::bail!("{} does not exist", args.db_path.display());
+    }
+    let files_path = args
+        .files_path
+        .clone()
+        .or_else(|| guzuta::files_path(&args.db_path).filter(|path| path.exists()));
+    let db_repo = load_repository(args.db_path.clone())?;
+    let verifier = guzuta::Verifier::new();
+
+    let mut problems = guzuta::verify_packages(
+        &db_repo,
+        db_dir(&args.db_path),
+        &verifier,
+        args.require_signatures,
+    );
+    if let Some(files_path) = files_path {
+        let files_repo = load_repository(files_path)?;
+        problems.extend(guzuta::compare_databases(&db_repo, &files_repo));
+    }
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} problems found in {}",
+            problems.len(),
+            args.db_path.display()
+        ))
+    }
+}
+
+fn package_verify(args: PackageVerifyArgs) -> Result<()> {
+    let package = guzuta::Package::load(&args.package_path)
+        .with_context(|| format!("Unable to load package {}", args.package_path.display()))?;
+    let problems = guzuta::verify_package_contents(&package, &args.package_path)
+        .with_context(|| format!("Unable to read package {}", args.package_path.display()))?;
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} problems found in {}",
+            problems.len(),
+            args.package_path.display()
+        ))
+    }
+}
+
+fn repo_check_deps(args: RepoCheckDepsArgs) -> Result<()> {
+    if !args.db_path.exists() {
+        anyhow::bail!("{} does not exist", args.db_path.display());
+    }
+    let repository = load_repository(args.db_path.clone())?;
+    let mut sync_repositories = vec![];
+    for sync_db_path in args.sync_db {
+        if !sync_db_path.exists() {
+            anyhow::bail!("{} does not exist", sync_db_path.display());
+        }
+        sync_repositories.push(load_repository(sync_db_path)?);
+    }
+
+    let problems = guzuta::check_depends(&repository, &sync_repositories);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} problems found in {}",
+            problems.len(),
+            args.db_path.display()
+        ))
+    }
+}
+
+fn rebuild_list(args: RebuildListArgs) -> Result<()> {
+    let repository = load_repository(args.db_path)?;
+    for pkgbase in repository.rebuild_list(&args.package_name) {
+        println!("{}", pkgbase);
+    }
+    Ok(())
+}
+
+fn package_lint(args: PackageLintArgs) -> Result<()> {
+    let package = guzuta::Package::load_with_elf(&args.package_path)
+        .with_context(|| format!("Unable to load package {}", args.package_path.display()))?;
+    let mut repositories = vec![];
+    for db_path in args.db {
+        if !db_path.exists() {
+            anyhow::bail!("{} does not exist", db_path.display());
+        }
+        repositories.push(load_repository(db_path)?);
+    }
+
+    let findings = args.lint_severity.linter().lint(&package);
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let problems = guzuta::check_sonames(&package, &repositories);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == guzuta::Severity::Error)
+        .count()
+        + problems.len();
+    if errors == 0 {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} problems found in {}",
+            errors,
+            args.package_path.display()
+        ))
+    }
+}
+
+async fn package_create(args: PackageCreateArgs) -> Result<()> {
+    let pkginfo = args.pkginfo()?;
+    let path =
+        guzuta::create_package(&pkginfo, &args.staging_dir, &args.out_dir).with_context(|| {
+            format!(
+                "Unable to create package from {}",
+                args.staging_dir.display()
+            )
+        })?;
+    if let Some(signer) = args.signer.signer(&args.package_key)? {
+        let mut sig_path = path.clone().into_os_string();
+        sig_path.push(".sig");
+        signer.sign(&path, std::path::Path::new(&sig_path)).await?;
+    }
+    println!("{}", path.display());
+    Ok(())
+}
+
+async fn repo_rebuild(args: RepoRebuildArgs) -> Result<()> {
+    let mut package_paths = vec![];
+    for filename in list_dir(&args.repo_dir)? {
+        let filename = filename.to_string_lossy();
+        if filename.contains(".pkg.tar") && !filename.ends_with(".sig") {
+            package_paths.push(args.repo_dir.join(filename.as_ref()));
+        }
+    }
+
+    let parallelism = std::thread::available_parallelism().map_or(1, |n| n.get());
+    use futures::StreamExt as _;
+    let mut results = futures::stream::iter(package_paths)
+        .map(|path| {
+            tokio::task::spawn_blocking(move || {
+                guzuta::Package::load(&path)
+                    .with_context(|| format!("Unable to load package {}", path.display()))
+            })
+        })
+        .buffer_unordered(parallelism);
+    let mut packages: std::collections::HashMap<String, guzuta::Package> =
+        std::collections::HashMap::new();
+    while let Some(result) = results.next().await {
+        let package = result??;
+        match packages.get(package.pkgname()) {
+            Some(newest) if newest.version() >= package.version() => {}
+            _ => {
+                packages.insert(package.pkgname().to_owned(), package);
+            }
+        }
+    }
+
+    let repo_signer = args.signer.signer(&args.repo_key)?;
+    let mut db_path = args.repo_dir.join(&args.repo_name).into_os_string();
+    db_path.push(".db");
+    let mut repository = guzuta::RepositoryPair::from_db_path(
+        std::path::PathBuf::from(db_path),
+        repo_signer.as_deref(),
+    )?;
+    repository.set_compression(
+        args.db_compression.compression_or(repository.compression()),
+        args.db_compression.level,
+    );
+    repository.lock(args.lock.timeout()).await?;
+    for package in packages.values() {
+        repository.add(package, guzuta::AddMode::Force)?;
+    }
+    repository.save().await?;
+    println!(
+        "Added {} packages to {}",
+        packages.len(),
+        repository.db().path().display()
+    );
+    Ok(())
+}
+
+async fn repo_resign(args: RepoResignArgs) -> Result<()> {
+    if !args.db_path.exists() {
+        return Err(anyhow::anyhow!("{} not found", args.db_path.display()));
+    }
+    let package_signer = args.signer.signer(&args.package_key)?;
+    let repo_signer = args.signer.signer(&args.repo_key)?;
+    let mut repository =
+        guzuta::RepositoryPair::from_db_path(args.db_path.clone(), repo_signer.as_deref())?;
+    repository.set_compression(
+        args.db_compression.compression_or(repository.compression()),
+        args.db_compression.level,
+    );
+
+    repository.lock(args.lock.timeout()).await?;
+    repository.load()?;
+    let sig_paths = resign_repository(
+        &mut repository,
+        db_dir(&args.db_path),
+        package_signer.as_deref(),
+        args.keep_old.keep_old_signatures,
+    )
+    .await?;
+    println!(
+        "Re-signed {} packages in {}",
+        sig_paths.len(),
+        repository.db().path().display()
+    );
+    Ok(())
+}
+
+/// Re-sign package files in `repo_dir` listed in the loaded `repository` with `package_signer`,
+/// update their PGPSIG and save the databases. Package signatures are replaced only after the
+/// databases are saved. Returns the paths of the package signatures.
+async fn resign_repository(
+    repository: &mut guzuta::RepositoryPair<'_>,
+    repo_dir: &std::path::Path,
+    package_signer: Option<&dyn guzuta::Signer>,
+    keep_old: bool,
+) -> Result<Vec<std::path::PathBuf>> {
+    let mut db_sig_path = repository.db().path().as_os_str().to_os_string();
+    db_sig_path.push(".sig");
+    if !repository.db().has_signer() && std::path::Path::new(&db_sig_path).exists() {
+        return Err(anyhow::anyhow!(
+            "{} is signed, so a repository key is required to re-sign it",
+            repository.db().path().display()
+        ));
+    }
+
+    let mut signatures = vec![];
+    let result = async {
+        if let Some(signer) = package_signer {
+            let entries: Vec<_> = repository
+                .db()
+                .entries()
+                .map(|entry| {
+                    (
+                        entry.desc.pkgname().to_owned(),
+                        repo_dir.join(entry.desc.filename()),
+                    )
+                })
+                .collect();
+            for (pkgname, path) in entries {
+                let mut sig_path = path.clone().into_os_string();
+                sig_path.push(".sig");
+                let sig_path = std::path::PathBuf::from(sig_path);
+                let mut progress_path = sig_path.clone().into_os_string();
+                progress_path.push(".progress");
+                let progress_path = std::path::PathBuf::from(progress_path);
+                signatures.push((progress_path.clone(), sig_path.clone()));
+
+                signer
+                    .sign(&path, &progress_path)
+                    .await
+                    .with_context(|| format!("Unable to sign {}", path.display()))?;
+                let mut signature = std::fs::read(&progress_path)?;
+                if keep_old {
+                    match std::fs::read(&sig_path) {
+                        Ok(old_signature) => {
+                            signature = guzuta::append_old_signatures(&signature, &old_signature)
+                                .with_context(|| {
+                                format!("Unable to parse {}", sig_path.display())
+                            })?;
+                            std::fs::write(&progress_path, &signature)?;
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(e) => {
+                            return Err(anyhow::Error::from(e))
+                                .with_context(|| format!("Unable to read {}", sig_path.display()))
+                        }
+                    }
+                }
+                repository.set_signature(&pkgname, &signature)?;
+            }
+        }
+        repository.save().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            for (progress_path, sig_path) in &signatures {
+                std::fs::rename(progress_path, sig_path)
+                    .with_context(|| format!("Unable to rename {}", progress_path.display()))?;
+            }
+            Ok(signatures
+                .into_iter()
+                .map(|(_, sig_path)| sig_path)
+                .collect())
+        }
+        Err(e) => {
+            for (progress_path, _) in &signatures {
+                let _ = std::fs::remove_file(progress_path);
+            }
+            Err(e)
+        }
+    }
+}
+
 async fn omakase_build(args: OmakaseBuildArgs) {
     let file = std::fs::File::open(".guzuta.yml").expect("Unable to open .guzuta.yml");
     let config =
         guzuta::omakase::Config::from_reader(file).expect("Unable to load YAML from .guzuta.yml");
-    let package_signer = config.package_key.as_deref().map(guzuta::Signer::new);
-    let repo_signer = config.repo_key.as_deref().map(guzuta::Signer::new);
-    let builder = guzuta::Builder::new(package_signer, &config.srcdest, &config.logdest);
+    let package_signer = config
+        .signer
+        .combined_signer(&config.package_key)
+        .unwrap_or_else(|e| panic!("{:#}", e));
+    let repo_signer = config
+        .signer
+        .combined_signer(&config.repo_key)
+        .unwrap_or_else(|e| panic!("{:#}", e));
+    let verifier = omakase_verifier(&config, args.skip_verify);
+    let mut builder =
+        guzuta::Builder::new(package_signer.as_deref(), &config.srcdest, &config.logdest);
+    if let Some(linter) = config.linter() {
+        builder.set_linter(linter);
+    }
     let s3 = if let Some(ref s3_config) = config.s3 {
         Some(guzuta::omakase::S3::new(s3_config.clone()).await)
     } else {
@@ -329,27 +1617,6 @@ async fn omakase_build(args: OmakaseBuildArgs) {
             )
         });
 
-        if let Some(ref s3) = s3 {
-            s3.download_repository(&config, arch)
-                .await
-                .expect("Unable to download files from S3");
-        }
-
-        let mut db_repo = guzuta::Repository::new(config.db_path(arch), repo_signer);
-        let mut files_repo = guzuta::Repository::new(config.files_path(arch), repo_signer);
-        db_repo.load().unwrap_or_else(|_| {
-            panic!(
-                "Unable to load database repository from {}",
-                db_repo.path().display()
-            )
-        });
-        files_repo.load().unwrap_or_else(|_| {
-            panic!(
-                "Unable to load files repository from {}",
-                files_repo.path().display()
-            )
-        });
-
         let package_paths = builder
             .build_package(package_dir.as_path(), repo_dir, chroot)
             .await
@@ -359,31 +1626,99 @@ async fn omakase_build(args: OmakaseBuildArgs) {
                     package_dir.as_path().display()
                 )
             });
-        for path in &package_paths {
-            let package = guzuta::Package::load(path)
-                .unwrap_or_else(|_| panic!("Unable to load package {}", path.display()));
-            db_repo.add(&package);
-            files_repo.add(&package);
-        }
 
-        db_repo.save(false).await.unwrap_or_else(|_| {
-            panic!(
-                "Unable to save database repository to {}",
-                db_repo.path().display()
-            )
-        });
-        files_repo.save(true).await.unwrap_or_else(|_| {
-            panic!(
-                "Unable to save files repository to {}",
-                files_repo.path().display()
-            )
-        });
+        let mut repository = guzuta::RepositoryPair::new(
+            config.db_path(arch),
+            config.files_path(arch),
+            repo_signer.as_deref(),
+        );
+        repository.set_compression(config.db_compression, config.db_compression_level);
+        // Keep the lock until the updated databases are uploaded
+        repository
+            .lock(args.lock.timeout())
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        if let Some(ref s3) = s3 {
+            s3.download_repository(&config, arch)
+                .await
+                .expect("Unable to download files from S3");
+        }
+        if let Some(ref verifier) = verifier {
+            repository.set_verifier(verifier.clone());
+        }
+        let displaced = add_packages(
+            &mut repository,
+            &package_paths,
+            args.add_mode.mode(),
+            None,
+            None,
+        )
+        .await
+        .unwrap_or_else(|e| panic!("{:#}", e));
 
         if let Some(ref s3) = s3 {
             s3.upload_repository(&config, arch, &package_paths)
                 .await
                 .expect("Unable to upload files to S3");
         }
+
+        if args.remove_old.remove_old {
+            let existing = omakase_list_repository(&config, arch, s3.as_ref()).await;
+            let mut old_files = vec![];
+            for (package, filename) in displaced {
+                old_files.extend(args.remove_old.select(
+                    package.pkgname(),
+                    filename,
+                    Some(package.filename()),
+                    &existing,
+                ));
+            }
+            omakase_remove_packages(&config, arch, s3.as_ref(), &old_files).await;
+        }
+    }
+}
+
+/// Verifier of databases downloaded from S3, which are signed when repo_key is given
+fn omakase_verifier(
+    config: &guzuta::omakase::Config,
+    skip_verify: bool,
+) -> Option<guzuta::Verifier> {
+    if skip_verify || config.s3.is_none() || config.repo_key.is_empty() {
+        return None;
+    }
+    Some(
+        config
+            .verify
+            .verifier()
+            .unwrap_or_else(|e| panic!("{:#}", e)),
+    )
+}
+
+async fn omakase_list_repository(
+    config: &guzuta::omakase::Config,
+    arch: guzuta::Arch,
+    s3: Option<&guzuta::omakase::S3>,
+) -> Vec<std::ffi::OsString> {
+    if let Some(s3) = s3 {
+        s3.list_repository(config, arch)
+            .await
+            .expect("Unable to list files in S3")
+    } else {
+        list_dir(config.repo_dir(arch)).expect("Unable to list repository directory")
+    }
+}
+
+async fn omakase_remove_packages(
+    config: &guzuta::omakase::Config,
+    arch: guzuta::Arch,
+    s3: Option<&guzuta::omakase::S3>,
+    filenames: &[std::ffi::OsString],
+) {
+    remove_package_files(config.repo_dir(arch), filenames).expect("Unable to remove old packages");
+    if let Some(s3) = s3 {
+        s3.delete_packages(config, arch, filenames)
+            .await
+            .expect("Unable to delete files from S3");
     }
 }
 
@@ -391,7 +1726,11 @@ async fn omakase_remove(args: OmakaseRemoveArgs) {
     let file = std::fs::File::open(".guzuta.yml").expect("Unable to open .guzuta.yml");
     let config =
         guzuta::omakase::Config::from_reader(file).expect("Unable to load YAML from .guzuta.yml");
-    let repo_signer = config.repo_key.as_deref().map(guzuta::Signer::new);
+    let repo_signer = config
+        .signer
+        .combined_signer(&config.repo_key)
+        .unwrap_or_else(|e| panic!("{:#}", e));
+    let verifier = omakase_verifier(&config, args.skip_verify);
     let s3 = if let Some(ref s3_config) = config.s3 {
         Some(guzuta::omakase::S3::new(s3_config.clone()).await)
     } else {
@@ -399,43 +1738,126 @@ async fn omakase_remove(args: OmakaseRemoveArgs) {
     };
 
     for &arch in config.builds.keys() {
+        let mut repository = guzuta::RepositoryPair::new(
+            config.db_path(arch),
+            config.files_path(arch),
+            repo_signer.as_deref(),
+        );
+        repository.set_compression(config.db_compression, config.db_compression_level);
+        // Keep the lock until the updated databases are uploaded
+        repository
+            .lock(args.lock.timeout())
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
         if let Some(ref s3) = s3 {
             s3.download_repository(&config, arch)
                 .await
                 .expect("Unable to download files from S3");
         }
+        if let Some(ref verifier) = verifier {
+            repository.set_verifier(verifier.clone());
+        }
+        repository.load().unwrap_or_else(|e| panic!("{:#}", e));
 
-        let mut db_repo = guzuta::Repository::new(config.db_path(arch), repo_signer);
-        let mut files_repo = guzuta::Repository::new(config.files_path(arch), repo_signer);
-        db_repo.load().unwrap_or_else(|_| {
-            panic!(
-                "Unable to load database repository from {}",
-                db_repo.path().display()
-            )
-        });
-        files_repo.load().unwrap_or_else(|_| {
-            panic!(
-                "Unable to load files repository from {}",
-                files_repo.path().display()
-            )
-        });
+        let filename = repository.remove(&args.package_name);
+        repository
+            .save()
+            .await
+            .unwrap_or_else(|e| panic!("{:#}", e));
 
-        db_repo.remove(&args.package_name);
-        files_repo.remove(&args.package_name);
-        db_repo.save(false).await.unwrap_or_else(|_| {
-            panic!(
-                "Unable to save database repository to {}",
-                db_repo.path().display()
-            )
-        });
-        files_repo.save(true).await.unwrap_or_else(|_| {
-            panic!(
-                "Unable to save files repository to {}",
-                files_repo.path().display()
-            )
-        });
+        if let Some(ref s3) = s3 {
+            let paths: Vec<&str> = vec![];
+            s3.upload_repository(&config, arch, &paths)
+                .await
+                .expect("Unable to upload files to S3");
+        }
 
+        if args.remove_old.remove_old {
+            let existing = omakase_list_repository(&config, arch, s3.as_ref()).await;
+            let old_files = args
+                .remove_old
+                .select(&args.package_name, filename, None, &existing);
+            omakase_remove_packages(&config, arch, s3.as_ref(), &old_files).await;
+        }
+    }
+}
+
+async fn omakase_resign(args: OmakaseResignArgs) {
+    let file = std::fs::File::open(".guzuta.yml").expect("Unable to open .guzuta.yml");
+    let config =
+        guzuta::omakase::Config::from_reader(file).expect("Unable to load YAML from .guzuta.yml");
+    let package_signer = config
+        .signer
+        .combined_signer(&config.package_key)
+        .unwrap_or_else(|e| panic!("{:#}", e));
+    let repo_signer = config
+        .signer
+        .combined_signer(&config.repo_key)
+        .unwrap_or_else(|e| panic!("{:#}", e));
+    let verifier = omakase_verifier(&config, args.skip_verify);
+    let s3 = if let Some(ref s3_config) = config.s3 {
+        Some(guzuta::omakase::S3::new(s3_config.clone()).await)
+    } else {
+        None
+    };
+
+    for &arch in config.builds.keys() {
+        let mut repository = guzuta::RepositoryPair::new(
+            config.db_path(arch),
+            config.files_path(arch),
+            repo_signer.as_deref(),
+        );
+        repository.set_compression(config.db_compression, config.db_compression_level);
+        // Keep the lock until the updated databases are uploaded
+        repository
+            .lock(args.lock.timeout())
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
         if let Some(ref s3) = s3 {
+            s3.download_repository(&config, arch)
+                .await
+                .expect("Unable to download files from S3");
+        }
+        if let Some(ref verifier) = verifier {
+            repository.set_verifier(verifier.clone());
+        }
+        if !config.db_path(arch).exists() {
+            println!(
+                "Skip {} since it doesn't exist",
+                config.db_path(arch).display()
+            );
+            continue;
+        }
+        repository.load().unwrap_or_else(|e| panic!("{:#}", e));
+
+        if let (Some(s3), Some(_)) = (&s3, &package_signer) {
+            let filenames: Vec<_> = repository
+                .db()
+                .entries()
+                .map(|entry| entry.desc.filename().to_owned())
+                .collect();
+            s3.download_packages(&config, arch, &filenames)
+                .await
+                .expect("Unable to download packages from S3");
+        }
+        let sig_paths = resign_repository(
+            &mut repository,
+            &config.repo_dir(arch),
+            package_signer.as_deref(),
+            args.keep_old.keep_old_signatures,
+        )
+        .await
+        .unwrap_or_else(|e| panic!("{:#}", e));
+        println!(
+            "Re-signed {} packages in {}",
+            sig_paths.len(),
+            repository.db().path().display()
+        );
+
+        if let Some(ref s3) = s3 {
+            s3.upload_signatures(&sig_paths)
+                .await
+                .expect("Unable to upload files to S3");
             let paths: Vec<&str> = vec![];
             s3.upload_repository(&config, arch, &paths)
                 .await
@@ -1,13 +1,72 @@
 #[derive(Debug, serde::Deserialize)]
 pub struct Config {
     pub name: String,
-    pub package_key: Option<String>,
-    pub repo_key: Option<String>,
+    /// Keys to sign packages, either a key or a list of keys
+    #[serde(default, deserialize_with = "deserialize_keys")]
+    pub package_key: Vec<String>,
+    /// Keys to sign repository databases, either a key or a list of keys
+    #[serde(default, deserialize_with = "deserialize_keys")]
+    pub repo_key: Vec<String>,
+    /// Backend to sign with package_key and repo_key
+    #[serde(default)]
+    pub signer: super::signer::SignerConfig,
+    /// Verification of the downloaded databases signed with repo_key
+    #[serde(default)]
+    pub verify: super::signer::VerifyConfig,
     pub srcdest: std::path::PathBuf,
     pub logdest: std::path::PathBuf,
     pub pkgbuild: String,
     pub builds: std::collections::HashMap<super::builder::Arch, BuildConfig>,
     pub s3: Option<S3Config>,
+    #[serde(
+        default = "default_db_compression",
+        deserialize_with = "deserialize_db_compression"
+    )]
+    pub db_compression: super::compression::Compression,
+    pub db_compression_level: Option<u32>,
+    /// Severities of lint rules. Built packages are linted only when this is given.
+    pub lint: Option<std::collections::HashMap<super::lint::Rule, super::lint::Severity>>,
+}
+
+fn deserialize_keys<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Keys {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    use serde::Deserialize as _;
+    Ok(match Option::<Keys>::deserialize(deserializer)? {
+        None => vec![],
+        Some(Keys::One(key)) => vec![key],
+        Some(Keys::Many(keys)) => keys,
+    })
+}
+
+fn default_db_compression() -> super::compression::Compression {
+    super::compression::Compression::Gzip
+}
+
+fn deserialize_db_compression<'de, D>(
+    deserializer: D,
+) -> Result<super::compression::Compression, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize as _;
+    let compression = super::compression::Compression::deserialize(deserializer)?;
+    if compression.is_writable() {
+        Ok(compression)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "Unsupported compression for writing: {}",
+            compression
+        )))
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -29,6 +88,16 @@ impl Config {
         serde_yaml::from_reader(reader)
     }
 
+    pub fn linter(&self) -> Option<super::lint::Linter> {
+        self.lint.as_ref().map(|severities| {
+            let mut linter = super::lint::Linter::new();
+            for (&rule, &severity) in severities {
+                linter.set_severity(rule, severity);
+            }
+            linter
+        })
+    }
+
     pub fn repo_dir(&self, arch: super::builder::Arch) -> std::path::PathBuf {
         std::path::PathBuf::from(&self.name)
             .join("os")
@@ -47,6 +116,14 @@ impl Config {
         std::path::PathBuf::from(path)
     }
 
+    pub fn db_archive_path(&self, arch: super::builder::Arch) -> std::path::PathBuf {
+        super::repository::archive_path(self.db_path(arch), self.db_compression)
+    }
+
+    pub fn files_archive_path(&self, arch: super::builder::Arch) -> std::path::PathBuf {
+        super::repository::archive_path(self.files_path(arch), self.db_compression)
+    }
+
     pub fn package_dir(&self, package_name: &str) -> std::path::PathBuf {
         std::path::PathBuf::from(&self.pkgbuild).join(package_name)
     }
@@ -76,12 +153,62 @@ impl S3 {
         config: &Config,
         arch: super::builder::Arch,
     ) -> Result<(), anyhow::Error> {
-        let (r1, r2) = futures::join!(
-            self.get(config.db_path(arch)),
-            self.get(config.files_path(arch))
-        );
-        r1?;
-        r2
+        let mut futures_unordered = futures::stream::FuturesUnordered::new();
+        for path in [config.db_path(arch), config.files_path(arch)] {
+            if !config.repo_key.is_empty() {
+                let mut sig_path = path.clone().into_os_string();
+                sig_path.push(".sig");
+                futures_unordered.push(self.get(std::path::PathBuf::from(sig_path)));
+            }
+            futures_unordered.push(self.get(path));
+        }
+        use futures::StreamExt as _;
+        while let Some(result) = futures_unordered.next().await {
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Download package files and their signatures from the repository directory of `arch`
+    pub async fn download_packages<S>(
+        &self,
+        config: &Config,
+        arch: super::builder::Arch,
+        filenames: &[S],
+    ) -> Result<(), anyhow::Error>
+    where
+        S: AsRef<std::path::Path>,
+    {
+        let repo_dir = config.repo_dir(arch);
+        let mut futures_unordered = futures::stream::FuturesUnordered::new();
+        for filename in filenames {
+            let path = repo_dir.join(filename);
+            let mut sig_path = path.clone().into_os_string();
+            sig_path.push(".sig");
+            futures_unordered.push(self.get(path));
+            futures_unordered.push(self.get(std::path::PathBuf::from(sig_path)));
+        }
+        use futures::StreamExt as _;
+        while let Some(result) = futures_unordered.next().await {
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Upload signatures of packages without the packages themselves
+    pub async fn upload_signatures<P>(&self, sig_paths: &[P]) -> Result<(), anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let mut futures_unordered = futures::stream::FuturesUnordered::new();
+        for sig_path in sig_paths {
+            futures_unordered.push(self.put(sig_path.as_ref(), "application/pgp-signature"));
+        }
+        use futures::StreamExt as _;
+        while let Some(result) = futures_unordered.next().await {
+            result?;
+        }
+        Ok(())
     }
 
     pub async fn upload_repository<P>(
@@ -97,7 +224,6 @@ impl S3 {
         const ZST_MIME_TYPE: &str = "application/zstd";
         const OCTET_STREAM_MIME_TYPE: &str = "application/octet-stream";
         const SIG_MIME_TYPE: &str = "application/pgp-signature";
-        const GZIP_MIME_TYPE: &str = "application/gzip";
 
         let mut futures_unordered = futures::stream::FuturesUnordered::new();
         for package_path in package_paths {
@@ -113,19 +239,78 @@ impl S3 {
                 OCTET_STREAM_MIME_TYPE
             };
             futures_unordered.push(self.put(package_path.as_ref().to_owned(), mime_type));
-            if config.package_key.is_some() {
+            if !config.package_key.is_empty() {
                 let mut sig_path = package_path.as_ref().as_os_str().to_os_string();
                 sig_path.push(".sig");
                 futures_unordered.push(self.put(std::path::PathBuf::from(sig_path), SIG_MIME_TYPE));
             }
         }
-        futures_unordered.push(self.put(config.files_path(arch), GZIP_MIME_TYPE));
-        let db_path = config.db_path(arch);
-        futures_unordered.push(self.put(db_path.to_owned(), GZIP_MIME_TYPE));
-        if config.repo_key.is_some() {
-            let mut sig_path = db_path.into_os_string();
+        // S3 has no symlinks, so both foo.db and foo.db.tar.* are uploaded as regular objects
+        let db_mime_type = config.db_compression.mime_type();
+        for db_path in [
+            config.db_path(arch),
+            config.db_archive_path(arch),
+            config.files_path(arch),
+            config.files_archive_path(arch),
+        ] {
+            futures_unordered.push(self.put(db_path.to_owned(), db_mime_type));
+            if !config.repo_key.is_empty() {
+                let mut sig_path = db_path.into_os_string();
+                sig_path.push(".sig");
+                futures_unordered.push(self.put(std::path::PathBuf::from(sig_path), SIG_MIME_TYPE));
+            }
+        }
+        use futures::StreamExt as _;
+        while let Some(result) = futures_unordered.next().await {
+            result?;
+        }
+        Ok(())
+    }
+
+    /// List filenames of objects in the repository directory of `arch`
+    pub async fn list_repository(
+        &self,
+        config: &Config,
+        arch: super::builder::Arch,
+    ) -> Result<Vec<std::ffi::OsString>, anyhow::Error> {
+        let repo_dir = config.repo_dir(arch);
+        let prefix = format!("{}/", repo_dir.to_string_lossy());
+        let mut paginator = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&prefix)
+            .into_paginator()
+            .send();
+        let mut filenames = vec![];
+        while let Some(page) = paginator.next().await {
+            for object in page?.contents() {
+                if let Some(filename) = object.key().and_then(|key| key.strip_prefix(&prefix)) {
+                    filenames.push(std::ffi::OsString::from(filename));
+                }
+            }
+        }
+        Ok(filenames)
+    }
+
+    /// Delete package files and their signatures from the repository directory of `arch`
+    pub async fn delete_packages<S>(
+        &self,
+        config: &Config,
+        arch: super::builder::Arch,
+        filenames: &[S],
+    ) -> Result<(), anyhow::Error>
+    where
+        S: AsRef<std::path::Path>,
+    {
+        let repo_dir = config.repo_dir(arch);
+        let mut futures_unordered = futures::stream::FuturesUnordered::new();
+        for filename in filenames {
+            let path = repo_dir.join(filename);
+            let mut sig_path = path.clone().into_os_string();
             sig_path.push(".sig");
-            futures_unordered.push(self.put(std::path::PathBuf::from(sig_path), SIG_MIME_TYPE));
+            futures_unordered.push(self.delete(path));
+            futures_unordered.push(self.delete(std::path::PathBuf::from(sig_path)));
         }
         use futures::StreamExt as _;
         while let Some(result) = futures_unordered.next().await {
@@ -183,4 +368,19 @@ impl S3 {
         request.send().await?;
         Ok(())
     }
+
+    async fn delete<P>(&self, path: P) -> Result<(), anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let path = path.as_ref();
+        println!("Delete {}", path.display());
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(path.to_string_lossy())
+            .send()
+            .await?;
+        Ok(())
+    }
 }
@@ -1,15 +1,20 @@
+use anyhow::Context as _;
 use md5::Digest as _;
 use std::io::Read;
 
 #[derive(Debug, Clone)]
 pub struct Package {
     pkginfo: PkgInfo,
+    buildinfo: Option<BuildInfo>,
+    mtree: Option<Vec<MtreeEntry>>,
+    mtree_error: Option<String>,
     size: u64,
     filename: std::ffi::OsString,
     pgpsig: String,
     md5sum: String,
     sha256sum: String,
     files: Vec<std::path::PathBuf>,
+    elf_files: Vec<ElfFile>,
 }
 
 impl Package {
@@ -17,48 +22,92 @@ impl Package {
     where
         P: AsRef<std::path::Path>,
     {
-        let path = path.as_ref();
-        let (pkginfo, files) = PkgInfo::load(path)?;
+        Self::load_package(path.as_ref(), false)
+    }
+
+    /// Like `load`, but also parse ELF files for `elf_files` and soname checks, which reads each
+    /// of them into memory
+    pub fn load_with_elf<P>(path: P) -> Result<Package, anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        Self::load_package(path.as_ref(), true)
+    }
+
+    fn load_package(path: &std::path::Path, scan_elf: bool) -> Result<Package, anyhow::Error> {
+        let file = std::fs::File::open(path)?;
+        let mut package = Self::read(
+            file,
+            path.file_name()
+                .expect("Unable to find file_name from package path"),
+            scan_elf,
+        )
+        .with_context(|| format!("Unable to read package {}", path.display()))?;
         let mut sig_path = path.as_os_str().to_os_string();
         sig_path.push(".sig");
-        let pgpsig = if let Ok(mut f) = std::fs::File::open(sig_path) {
+        if let Ok(mut f) = std::fs::File::open(sig_path) {
             let mut buf = vec![];
             f.read_to_end(&mut buf)?;
-            use base64::Engine as _;
-            base64::engine::general_purpose::STANDARD.encode(&buf)
-        } else {
-            "".to_owned()
-        };
-        let mut md5 = md5::Md5::new();
-        let mut sha256 = sha2::Sha256::new();
-        let mut f = std::fs::File::open(path)?;
-        loop {
-            let mut buf = [0; 1024];
-            match f.read(&mut buf)? {
-                0 => {
-                    break;
-                }
-                len => {
-                    md5.update(&buf[..len]);
-                    sha256.update(&buf[..len]);
-                }
-            }
+            package.set_signature(&buf);
         }
+        Ok(package)
+    }
+
+    /// Load a package named `filename` from `reader` in a single pass, hashing the compressed
+    /// stream while parsing it. The package has no signature unless `set_signature` is called.
+    pub fn from_reader<R>(reader: R, filename: &std::ffi::OsStr) -> Result<Package, anyhow::Error>
+    where
+        R: Read,
+    {
+        Self::read(reader, filename, false)
+    }
+
+    fn read<R>(
+        reader: R,
+        filename: &std::ffi::OsStr,
+        scan_elf: bool,
+    ) -> Result<Package, anyhow::Error>
+    where
+        R: Read,
+    {
+        let mut hashing_reader = HashingReader::new(reader);
+        let Contents {
+            pkginfo,
+            buildinfo,
+            mtree,
+            mtree_error,
+            files,
+            elf_files,
+        } = {
+            let mut archive_reader = super::compression::decompress(&mut hashing_reader)?;
+            let contents = Contents::read(&mut archive_reader, scan_elf)?;
+            // Consume the rest such as tar padding so that the whole stream is hashed
+            std::io::copy(&mut archive_reader, &mut std::io::sink())?;
+            contents
+        };
+        std::io::copy(&mut hashing_reader, &mut std::io::sink())?;
 
         Ok(Package {
             pkginfo,
-            size: std::fs::metadata(path)?.len(),
-            filename: path
-                .file_name()
-                .expect("Unable to find file_name from package path")
-                .to_os_string(),
-            pgpsig,
-            md5sum: format!("{:x}", md5.finalize()),
-            sha256sum: format!("{:x}", sha256.finalize()),
+            buildinfo,
+            mtree,
+            mtree_error,
+            size: hashing_reader.size,
+            filename: filename.to_os_string(),
+            pgpsig: "".to_owned(),
+            md5sum: format!("{:x}", hashing_reader.md5.finalize()),
+            sha256sum: format!("{:x}", hashing_reader.sha256.finalize()),
             files,
+            elf_files,
         })
     }
 
+    /// Set the detached signature stored as PGPSIG
+    pub fn set_signature(&mut self, signature: &[u8]) {
+        use base64::Engine as _;
+        self.pgpsig = base64::engine::general_purpose::STANDARD.encode(signature);
+    }
+
     pub fn groups(&self) -> &Vec<String> {
         &self.pkginfo.groups
     }
@@ -80,6 +129,9 @@ impl Package {
     pub fn pkgver(&self) -> &str {
         &self.pkginfo.pkgver
     }
+    pub fn version(&self) -> super::version::Version {
+        super::version::Version::parse(&self.pkginfo.pkgver)
+    }
     pub fn pkgdesc(&self) -> &str {
         &self.pkginfo.pkgdesc
     }
@@ -132,13 +184,164 @@ impl Package {
     pub fn optdepends(&self) -> &Vec<String> {
         &self.pkginfo.optdepends
     }
+    pub fn parsed_depends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.pkginfo.depends)
+    }
+    pub fn parsed_makedepends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.pkginfo.makedepends)
+    }
+    pub fn parsed_checkdepends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.pkginfo.checkdepends)
+    }
+    pub fn parsed_optdepends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.pkginfo.optdepends)
+    }
+    pub fn parsed_provides(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.pkginfo.provides)
+    }
+    pub fn parsed_conflicts(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.pkginfo.conflicts)
+    }
+    pub fn parsed_replaces(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.pkginfo.replaces)
+    }
+    /// Whether this package satisfies `depend` by its name or provides
+    pub fn satisfies(&self, depend: &super::depend::Depend) -> bool {
+        depend.satisfied_by(self.pkgname(), self.pkgver(), &self.parsed_provides())
+    }
+    pub fn xdata(&self) -> &Vec<String> {
+        &self.pkginfo.xdata
+    }
+    /// Entries of .PKGINFO unknown to guzuta in the original order
+    pub fn extra_entries(&self) -> &Vec<(String, String)> {
+        &self.pkginfo.extra_entries
+    }
 
     pub fn files(&self) -> &Vec<std::path::PathBuf> {
         &self.files
     }
+
+    /// Build environment recorded in .BUILDINFO, which is missing in packages built by old makepkg
+    pub fn buildinfo(&self) -> Option<&BuildInfo> {
+        self.buildinfo.as_ref()
+    }
+
+    /// ELF files in the package, which are empty unless loaded by `load_with_elf`
+    pub fn elf_files(&self) -> &Vec<ElfFile> {
+        &self.elf_files
+    }
+
+    /// Soname provides like `libfoo.so=1-64` for shared libraries directly under /usr/lib or
+    /// /usr/lib32, in the form makepkg generates
+    pub fn soname_provides(&self) -> Vec<super::depend::Depend> {
+        let mut provides = vec![];
+        for elf_file in &self.elf_files {
+            let parent = elf_file.path.parent().unwrap_or(std::path::Path::new(""));
+            if parent != std::path::Path::new("usr/lib")
+                && parent != std::path::Path::new("usr/lib32")
+            {
+                continue;
+            }
+            if let Some(provide) = elf_file
+                .soname
+                .as_deref()
+                .and_then(|soname| soname_depend(soname, elf_file.bits()))
+            {
+                if !provides.contains(&provide) {
+                    provides.push(provide);
+                }
+            }
+        }
+        provides
+    }
+
+    /// Soname dependencies like `libbar.so=2-64` of DT_NEEDED entries, except for the sonames
+    /// shipped in this package
+    pub fn soname_depends(&self) -> Vec<super::depend::Depend> {
+        let mut depends = vec![];
+        for (soname, bits) in self.needed_sonames() {
+            if let Some(depend) = soname_depend(soname, bits) {
+                if !depends.contains(&depend) {
+                    depends.push(depend);
+                }
+            }
+        }
+        depends
+    }
+
+    /// DT_NEEDED entries along with the word size, except for the sonames shipped in this package
+    pub fn needed_sonames(&self) -> Vec<(&str, u32)> {
+        let mut needed = vec![];
+        for elf_file in &self.elf_files {
+            for soname in &elf_file.needed {
+                let shipped = self
+                    .elf_files
+                    .iter()
+                    .any(|f| f.bits() == elf_file.bits() && f.soname.as_ref() == Some(soname));
+                if !shipped && !needed.contains(&(soname.as_str(), elf_file.bits())) {
+                    needed.push((soname.as_str(), elf_file.bits()));
+                }
+            }
+        }
+        needed
+    }
+
+    /// Metadata of each file recorded in .MTREE, including .PKGINFO and .BUILDINFO
+    pub fn mtree(&self) -> Option<&Vec<MtreeEntry>> {
+        self.mtree.as_ref()
+    }
+
+    /// Why .MTREE was ignored when it exists but cannot be parsed
+    pub fn mtree_error(&self) -> Option<&str> {
+        self.mtree_error.as_deref()
+    }
 }
 
-#[derive(Debug, Default, Clone)]
+/// Metadata files and the file list read from a package
+struct Contents {
+    pkginfo: PkgInfo,
+    buildinfo: Option<BuildInfo>,
+    mtree: Option<Vec<MtreeEntry>>,
+    mtree_error: Option<String>,
+    files: Vec<std::path::PathBuf>,
+    elf_files: Vec<ElfFile>,
+}
+
+/// Computes checksums and the size of everything read through it
+struct HashingReader<R> {
+    inner: R,
+    md5: md5::Md5,
+    sha256: sha2::Sha256,
+    size: u64,
+}
+
+impl<R> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            md5: md5::Md5::new(),
+            sha256: sha2::Sha256::new(),
+            size: 0,
+        }
+    }
+}
+
+impl<R> Read for HashingReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.md5.update(&buf[..len]);
+        self.sha256.update(&buf[..len]);
+        self.size += len as u64;
+        Ok(len)
+    }
+}
+
+/// Contents of .PKGINFO. It can be also deserialized to describe a package to be created.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(default)]
 pub struct PkgInfo {
     pub pkgname: String,
     pub pkgbase: String,
@@ -159,30 +362,23 @@ pub struct PkgInfo {
     pub backups: Vec<String>,
     pub provides: Vec<String>,
     pub replaces: Vec<String>,
-    pub xdata: String,
+    pub xdata: Vec<String>,
+    #[serde(skip)]
+    pub extra_entries: Vec<(String, String)>,
 }
 
-impl PkgInfo {
-    fn load<P>(path: P) -> Result<(Self, Vec<std::path::PathBuf>), anyhow::Error>
+impl Contents {
+    fn read<R>(archive_reader: R, scan_elf: bool) -> Result<Self, anyhow::Error>
     where
-        P: AsRef<std::path::Path>,
+        R: Read,
     {
-        let file = std::fs::File::open(path.as_ref())?;
-        let archive_reader: Box<dyn std::io::Read> =
-            if let Some(extension) = path.as_ref().extension() {
-                if extension == "xz" {
-                    Box::new(lzma::LzmaReader::new_decompressor(file)?)
-                } else if extension == "zst" {
-                    Box::new(zstd::Decoder::new(file)?)
-                } else {
-                    panic!("Unknown file format: {}", path.as_ref().display())
-                }
-            } else {
-                panic!("Unknown file format: {}", path.as_ref().display())
-            };
         let mut tar_reader = tar::Archive::new(archive_reader);
         let mut pkginfo = None;
+        let mut buildinfo = None;
+        let mut mtree = None;
+        let mut mtree_error = None;
         let mut files = vec![];
+        let mut elf_files = vec![];
         for entry_result in tar_reader.entries()? {
             let mut entry = entry_result?;
             let path = entry.path()?.into_owned();
@@ -192,13 +388,59 @@ impl PkgInfo {
                 let mut body = String::new();
                 entry.read_to_string(&mut body)?;
                 pkginfo = Some(parse_pkginfo(&body)?);
+            } else if path.as_os_str() == ".BUILDINFO"
+                && entry.header().entry_type() == tar::EntryType::Regular
+            {
+                let mut buf = vec![];
+                entry.read_to_end(&mut buf)?;
+                // .BUILDINFO is informational, so packages with a newer format are still loaded
+                match std::str::from_utf8(&buf)
+                    .map_err(anyhow::Error::from)
+                    .and_then(parse_buildinfo)
+                {
+                    Ok(info) => buildinfo = Some(info),
+                    Err(e) => tracing::warn!("Ignoring malformed .BUILDINFO: {:#}", e),
+                }
+            } else if path.as_os_str() == ".MTREE"
+                && entry.header().entry_type() == tar::EntryType::Regular
+            {
+                let mut buf = vec![];
+                entry.read_to_end(&mut buf)?;
+                let parsed = super::compression::decompress(std::io::Cursor::new(buf)).and_then(
+                    |mut reader| {
+                        let mut body = String::new();
+                        reader.read_to_string(&mut body)?;
+                        parse_mtree(&body)
+                    },
+                );
+                // Only package-verify needs .MTREE, which reports `mtree_error`
+                match parsed {
+                    Ok(entries) => mtree = Some(entries),
+                    Err(e) => {
+                        tracing::warn!("Ignoring malformed .MTREE: {:#}", e);
+                        mtree_error = Some(format!("{:#}", e));
+                    }
+                }
             }
-            if !path.starts_with(".") {
+            // Skip metadata files such as .PKGINFO and .MTREE
+            if !path.to_string_lossy().starts_with('.') {
+                if scan_elf && entry.header().entry_type() == tar::EntryType::Regular {
+                    if let Some(elf_file) = ElfFile::read(&path, &mut entry)? {
+                        elf_files.push(elf_file);
+                    }
+                }
                 files.push(path.to_path_buf());
             }
         }
         if let Some(pkginfo) = pkginfo {
-            Ok((pkginfo, files))
+            Ok(Contents {
+                pkginfo,
+                buildinfo,
+                mtree,
+                mtree_error,
+                files,
+                elf_files,
+            })
         } else {
             Err(anyhow::anyhow!(".PKGINFO not found"))
         }
@@ -211,13 +453,7 @@ fn parse_pkginfo(body: &str) -> Result<PkgInfo, anyhow::Error> {
         if line.starts_with('#') {
             continue;
         }
-        let mut splitn = line.splitn(2, '=');
-        let key = splitn.next();
-        let val = splitn.next();
-        let rest = splitn.next();
-        if let (Some(key), Some(val), None) = (key, val, rest) {
-            let key = key.trim();
-            let val = val.trim();
+        if let Some((key, val)) = split_key_value(line) {
             match key {
                 "pkgname" => info.pkgname = val.to_owned(),
                 "pkgbase" => info.pkgbase = val.to_owned(),
@@ -238,8 +474,160 @@ fn parse_pkginfo(body: &str) -> Result<PkgInfo, anyhow::Error> {
                 "provides" => info.provides.push(val.to_owned()),
                 "backup" => info.backups.push(val.to_owned()),
                 "replaces" => info.replaces.push(val.to_owned()),
-                "xdata" => info.xdata = val.to_owned(),
-                _ => return Err(anyhow::anyhow!("Unknown PKGINFO entry '{}': {}", key, line)),
+                "xdata" => info.xdata.push(val.to_owned()),
+                _ => {
+                    tracing::warn!("Unknown PKGINFO entry '{}': {}", key, line);
+                    info.extra_entries.push((key.to_owned(), val.to_owned()));
+                }
+            }
+        } else {
+            return Err(anyhow::anyhow!("Invalid line: {}", line));
+        }
+    }
+    Ok(info)
+}
+
+/// Write .PKGINFO in the same order as makepkg
+pub fn into_pkginfo_file(info: &PkgInfo) -> Vec<u8> {
+    use std::io::Write as _;
+
+    let mut buf = vec![];
+    let mut write = |key: &str, val: &str| {
+        writeln!(buf, "{} = {}", key, val).expect("Writing to Vec never fails");
+    };
+    write("pkgname", &info.pkgname);
+    write(
+        "pkgbase",
+        if info.pkgbase.is_empty() {
+            &info.pkgname
+        } else {
+            &info.pkgbase
+        },
+    );
+    for xdata in &info.xdata {
+        write("xdata", xdata);
+    }
+    write("pkgver", &info.pkgver);
+    for (key, val) in [
+        ("pkgdesc", &info.pkgdesc),
+        ("url", &info.url),
+        ("builddate", &info.builddate.to_string()),
+        ("packager", &info.packager),
+        ("size", &info.size.to_string()),
+        ("arch", &info.arch),
+    ] {
+        if !val.is_empty() {
+            write(key, val);
+        }
+    }
+    for (key, vals) in [
+        ("license", &info.license),
+        ("replaces", &info.replaces),
+        ("group", &info.groups),
+        ("conflict", &info.conflicts),
+        ("provides", &info.provides),
+        ("backup", &info.backups),
+        ("depend", &info.depends),
+        ("optdepend", &info.optdepends),
+        ("makedepend", &info.makedepends),
+        ("checkdepend", &info.checkdepends),
+    ] {
+        for val in vals {
+            write(key, val);
+        }
+    }
+    for (key, val) in &info.extra_entries {
+        write(key, val);
+    }
+    buf
+}
+
+/// Split `key = value` line of .PKGINFO and .BUILDINFO
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let (key, val) = line.split_once('=')?;
+    Some((key.trim(), val.trim()))
+}
+
+/// Contents of .BUILDINFO
+#[derive(Debug, Default, Clone)]
+pub struct BuildInfo {
+    pub format: u32,
+    pub pkgname: String,
+    pub pkgbase: String,
+    pub pkgver: String,
+    pub pkgarch: String,
+    pub pkgbuild_sha256sum: String,
+    pub packager: String,
+    pub builddate: u64,
+    pub builddir: String,
+    pub startdir: String,
+    pub buildtool: String,
+    pub buildtoolver: String,
+    pub buildenv: Vec<String>,
+    pub options: Vec<String>,
+    pub installed: Vec<InstalledPackage>,
+    pub extra_entries: Vec<(String, String)>,
+}
+
+/// Package installed in the build environment, recorded as `installed = name-pkgver-pkgrel-arch`
+#[derive(Debug, Clone)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub arch: String,
+}
+
+impl InstalledPackage {
+    fn parse(s: &str) -> Option<Self> {
+        let mut rsplitn = s.rsplitn(4, '-');
+        let arch = rsplitn.next()?;
+        let pkgrel = rsplitn.next()?;
+        let pkgver = rsplitn.next()?;
+        let name = rsplitn.next()?;
+        Some(InstalledPackage {
+            name: name.to_owned(),
+            version: format!("{}-{}", pkgver, pkgrel),
+            arch: arch.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for InstalledPackage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}-{}-{}", self.name, self.version, self.arch)
+    }
+}
+
+fn parse_buildinfo(body: &str) -> Result<BuildInfo, anyhow::Error> {
+    let mut info = BuildInfo::default();
+    for line in body.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, val)) = split_key_value(line) {
+            match key {
+                "format" => info.format = val.parse()?,
+                "pkgname" => info.pkgname = val.to_owned(),
+                "pkgbase" => info.pkgbase = val.to_owned(),
+                "pkgver" => info.pkgver = val.to_owned(),
+                "pkgarch" => info.pkgarch = val.to_owned(),
+                "pkgbuild_sha256sum" => info.pkgbuild_sha256sum = val.to_owned(),
+                "packager" => info.packager = val.to_owned(),
+                "builddate" => info.builddate = val.parse()?,
+                "builddir" => info.builddir = val.to_owned(),
+                "startdir" => info.startdir = val.to_owned(),
+                "buildtool" => info.buildtool = val.to_owned(),
+                "buildtoolver" => info.buildtoolver = val.to_owned(),
+                "buildenv" => info.buildenv.push(val.to_owned()),
+                "options" => info.options.push(val.to_owned()),
+                "installed" => info.installed.push(
+                    InstalledPackage::parse(val)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid installed entry: {}", val))?,
+                ),
+                _ => {
+                    tracing::warn!("Unknown BUILDINFO entry '{}': {}", key, line);
+                    info.extra_entries.push((key.to_owned(), val.to_owned()));
+                }
             }
         } else {
             return Err(anyhow::anyhow!("Invalid line: {}", line));
@@ -247,3 +635,216 @@ fn parse_pkginfo(body: &str) -> Result<PkgInfo, anyhow::Error> {
     }
     Ok(info)
 }
+
+/// File entry of .MTREE
+#[derive(Debug, Clone)]
+pub struct MtreeEntry {
+    pub path: std::path::PathBuf,
+    pub file_type: MtreeType,
+    pub mode: Option<u32>,
+    pub uid: Option<u64>,
+    pub gid: Option<u64>,
+    pub size: Option<u64>,
+    pub mtime: Option<u64>,
+    pub link: Option<std::path::PathBuf>,
+    pub md5digest: Option<String>,
+    pub sha256digest: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MtreeType {
+    File,
+    Dir,
+    Link,
+    Other(String),
+}
+
+impl MtreeType {
+    fn parse(s: &str) -> Self {
+        match s {
+            "file" => MtreeType::File,
+            "dir" => MtreeType::Dir,
+            "link" => MtreeType::Link,
+            _ => MtreeType::Other(s.to_owned()),
+        }
+    }
+}
+
+impl std::fmt::Display for MtreeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            MtreeType::File => "file".fmt(f),
+            MtreeType::Dir => "dir".fmt(f),
+            MtreeType::Link => "link".fmt(f),
+            MtreeType::Other(s) => s.fmt(f),
+        }
+    }
+}
+
+// Parse the mtree(5) format written by bsdtar in makepkg
+fn parse_mtree(body: &str) -> Result<Vec<MtreeEntry>, anyhow::Error> {
+    let mut defaults = std::collections::HashMap::new();
+    let mut entries = vec![];
+    for line in body.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_ascii_whitespace();
+        let first = words.next().unwrap_or_default();
+        match first {
+            "/set" => {
+                for word in words {
+                    if let Some((key, val)) = word.split_once('=') {
+                        defaults.insert(key, val);
+                    }
+                }
+            }
+            "/unset" => {
+                for word in words {
+                    defaults.remove(word);
+                }
+            }
+            _ => {
+                let mut keywords = defaults.clone();
+                for word in words {
+                    if let Some((key, val)) = word.split_once('=') {
+                        keywords.insert(key, val);
+                    }
+                }
+                let path = mtree_unescape(first);
+                let path = path.strip_prefix("./").unwrap_or(&path).to_path_buf();
+                let invalid =
+                    |key: &str| anyhow::anyhow!("Invalid {} of {} in .MTREE", key, path.display());
+                let number = |key: &str| -> Result<Option<u64>, anyhow::Error> {
+                    keywords
+                        .get(key)
+                        .map(|val| val.parse().map_err(|_| invalid(key)))
+                        .transpose()
+                };
+                entries.push(MtreeEntry {
+                    file_type: MtreeType::parse(keywords.get("type").copied().unwrap_or("file")),
+                    mode: keywords
+                        .get("mode")
+                        .map(|val| u32::from_str_radix(val, 8).map_err(|_| invalid("mode")))
+                        .transpose()?,
+                    uid: number("uid")?,
+                    gid: number("gid")?,
+                    size: number("size")?,
+                    // Compare in whole seconds as tar headers do
+                    mtime: keywords
+                        .get("time")
+                        .map(|val| {
+                            let seconds = val.split('.').next().unwrap_or_default();
+                            seconds.parse().map_err(|_| invalid("time"))
+                        })
+                        .transpose()?,
+                    link: keywords.get("link").map(|val| mtree_unescape(val)),
+                    md5digest: keywords.get("md5digest").map(|val| (*val).to_owned()),
+                    sha256digest: keywords.get("sha256digest").map(|val| (*val).to_owned()),
+                    path,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// Decode \ooo escapes used for spaces and non-ASCII characters
+fn mtree_unescape(s: &str) -> std::path::PathBuf {
+    use std::os::unix::ffi::OsStringExt as _;
+
+    let bytes = s.as_bytes();
+    let mut buf = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes.get(i..i + 4) {
+            Some([b'\\', digits @ ..])
+                if (b'0'..=b'3').contains(&digits[0])
+                    && digits.iter().all(|b| (b'0'..=b'7').contains(b)) =>
+            {
+                buf.push(digits.iter().fold(0, |acc, b| acc << 3 | (b - b'0')));
+                i += 4;
+            }
+            _ => {
+                buf.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    std::path::PathBuf::from(std::ffi::OsString::from_vec(buf))
+}
+
+// Encode bytes other than printable ASCII, and characters special to mtree(5) as \ooo escapes
+pub fn mtree_escape(path: &std::path::Path) -> String {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let mut s = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_graphic() && b != b'\\' && b != b'#' && b != b'=' {
+            s.push(b as char);
+        } else {
+            s.push_str(&format!("\\{:03o}", b));
+        }
+    }
+    s
+}
+
+/// Dynamic section of an ELF file in a package
+#[derive(Debug, Clone)]
+pub struct ElfFile {
+    pub path: std::path::PathBuf,
+    pub soname: Option<String>,
+    /// DT_NEEDED entries
+    pub needed: Vec<String>,
+    pub is_64: bool,
+}
+
+impl ElfFile {
+    fn read<R>(path: &std::path::Path, reader: &mut R) -> Result<Option<Self>, anyhow::Error>
+    where
+        R: Read,
+    {
+        let mut buf = vec![];
+        reader.take(4).read_to_end(&mut buf)?;
+        if buf != b"\x7fELF" {
+            return Ok(None);
+        }
+        reader.read_to_end(&mut buf)?;
+        match goblin::elf::Elf::parse(&buf) {
+            Ok(elf) => Ok(Some(ElfFile {
+                path: path.to_path_buf(),
+                soname: elf.soname.map(|soname| soname.to_owned()),
+                needed: elf.libraries.iter().map(|lib| (*lib).to_owned()).collect(),
+                is_64: elf.is_64,
+            })),
+            Err(e) => {
+                tracing::warn!("Unable to parse ELF file {}: {}", path.display(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Word size used in soname provides and depends
+    pub fn bits(&self) -> u32 {
+        if self.is_64 {
+            64
+        } else {
+            32
+        }
+    }
+}
+
+/// `libfoo.so=1-64` for `libfoo.so.1` in a 64-bit ELF file
+pub fn soname_depend(soname: &str, bits: u32) -> Option<super::depend::Depend> {
+    let i = soname.find(".so")? + ".so".len();
+    let (name, rest) = soname.split_at(i);
+    // Unversioned sonames are not used in provides
+    let version = rest
+        .strip_prefix('.')
+        .filter(|version| !version.is_empty())?;
+    Some(super::depend::Depend::parse(&format!(
+        "{}={}-{}",
+        name, version, bits
+    )))
+}
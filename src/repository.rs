@@ -1,3 +1,4 @@
+use anyhow::Context as _;
 use std::io::Read;
 
 #[derive(Debug, Default, Clone)]
@@ -30,37 +31,445 @@ pub struct Desc {
     makedepends: Vec<String>,
     checkdepends: Vec<String>,
     optdepends: Vec<String>,
+    xdata: Vec<String>,
+
+    // Entries unknown to guzuta, written back verbatim
+    extra_entries: Vec<(String, Vec<String>)>,
 }
 
-#[derive(Clone)]
+impl Desc {
+    pub fn groups(&self) -> &Vec<String> {
+        &self.groups
+    }
+    pub fn license(&self) -> &Vec<String> {
+        &self.license
+    }
+    pub fn replaces(&self) -> &Vec<String> {
+        &self.replaces
+    }
+    pub fn filename(&self) -> &std::ffi::OsStr {
+        &self.filename
+    }
+    pub fn pkgname(&self) -> &str {
+        &self.name
+    }
+    pub fn pkgbase(&self) -> &str {
+        &self.base
+    }
+    pub fn pkgver(&self) -> &str {
+        &self.version
+    }
+    pub fn version(&self) -> super::version::Version {
+        super::version::Version::parse(&self.version)
+    }
+    pub fn pkgdesc(&self) -> &str {
+        &self.desc
+    }
+    pub fn csize(&self) -> u64 {
+        self.csize
+    }
+    pub fn isize(&self) -> u64 {
+        self.isize
+    }
+    pub fn md5sum(&self) -> &str {
+        &self.md5sum
+    }
+    pub fn sha256sum(&self) -> &str {
+        &self.sha256sum
+    }
+    pub fn pgpsig(&self) -> &str {
+        &self.pgpsig
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn arch(&self) -> &str {
+        &self.arch
+    }
+    pub fn builddate(&self) -> u64 {
+        self.builddate
+    }
+    pub fn packager(&self) -> &str {
+        &self.packager
+    }
+
+    pub fn conflicts(&self) -> &Vec<String> {
+        &self.conflicts
+    }
+    pub fn provides(&self) -> &Vec<String> {
+        &self.provides
+    }
+    pub fn depends(&self) -> &Vec<String> {
+        &self.depends
+    }
+    pub fn makedepends(&self) -> &Vec<String> {
+        &self.makedepends
+    }
+    pub fn checkdepends(&self) -> &Vec<String> {
+        &self.checkdepends
+    }
+    pub fn optdepends(&self) -> &Vec<String> {
+        &self.optdepends
+    }
+    pub fn parsed_depends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.depends)
+    }
+    pub fn parsed_makedepends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.makedepends)
+    }
+    pub fn parsed_checkdepends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.checkdepends)
+    }
+    pub fn parsed_optdepends(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.optdepends)
+    }
+    pub fn parsed_provides(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.provides)
+    }
+    pub fn parsed_conflicts(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.conflicts)
+    }
+    pub fn parsed_replaces(&self) -> Vec<super::depend::Depend> {
+        super::depend::parse_all(&self.replaces)
+    }
+    /// Whether this package satisfies `depend` by its name or provides
+    pub fn satisfies(&self, depend: &super::depend::Depend) -> bool {
+        depend.satisfied_by(self.pkgname(), self.pkgver(), &self.parsed_provides())
+    }
+    pub fn xdata(&self) -> &Vec<String> {
+        &self.xdata
+    }
+    /// Entries unknown to guzuta in the original order, e.g. `("BACKUP", ["etc/foo.conf"])`
+    pub fn extra_entries(&self) -> &Vec<(String, Vec<String>)> {
+        &self.extra_entries
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PackageEntry {
     pub desc: Desc,
     pub files: Vec<std::path::PathBuf>,
 }
 
+/// Query for `Repository::search_files`
+#[derive(Debug, Clone, Copy)]
+pub enum FileQuery<'q> {
+    /// Exact path, with or without the leading slash
+    Path(&'q std::path::Path),
+    /// File name without directories
+    Basename(&'q str),
+    /// Regular expression matched against paths without the leading slash
+    Regex(&'q regex::Regex),
+}
+
+impl FileQuery<'_> {
+    fn matches(&self, path: &std::path::Path) -> bool {
+        match *self {
+            FileQuery::Path(query) => path == query.strip_prefix("/").unwrap_or(query),
+            FileQuery::Basename(query) => {
+                // Directories are listed with a trailing slash
+                !path.to_string_lossy().ends_with('/')
+                    && path.file_name() == Some(std::ffi::OsStr::new(query))
+            }
+            FileQuery::Regex(query) => query.is_match(&path.to_string_lossy()),
+        }
+    }
+}
+
+/// How `Repository::add` treats a package whose name is already in the repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddMode {
+    /// Replace the existing entry unless the package is older than it
+    Default,
+    /// Always replace the existing entry, even when downgrading
+    Force,
+    /// Replace the existing entry only when the package is newer, like `repo-add -n`
+    NewerOnly,
+}
+
+/// Outcome of `Repository::add`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddResult {
+    /// The package was not added due to `AddMode::NewerOnly`
+    Skipped,
+    /// The package was added. Holds the package filename of the replaced entry, if any.
+    Added(Option<std::ffi::OsString>),
+}
+
 #[derive(Clone)]
 pub struct Repository<'a> {
     path: std::path::PathBuf,
-    signer: Option<super::signer::Signer<'a>>,
+    signer: Option<&'a dyn super::signer::Signer>,
+    verifier: Option<super::signer::Verifier>,
+    compression: super::compression::Compression,
+    compression_level: Option<u32>,
     entries: std::collections::HashMap<String, PackageEntry>,
+    lock: Option<std::sync::Arc<super::lock::Lock>>,
 }
 
 impl<'a> Repository<'a> {
-    pub fn new(path: std::path::PathBuf, signer: Option<super::signer::Signer<'a>>) -> Repository {
+    pub fn new(
+        path: std::path::PathBuf,
+        signer: Option<&'a dyn super::signer::Signer>,
+    ) -> Repository<'a> {
         Repository {
             path,
             signer,
+            verifier: None,
+            compression: super::compression::Compression::Gzip,
+            compression_level: None,
             entries: std::collections::HashMap::new(),
+            lock: None,
+        }
+    }
+
+    /// Take the exclusive lock of the repository, waiting up to `timeout` for other processes
+    /// such as repo-add. The lock is held until the repository is dropped, so call this before
+    /// `load` and keep the repository until `save` finishes.
+    /// foo.db and foo.files share foo.db.lck, so lock only one of them when modifying both.
+    pub async fn lock(&mut self, timeout: std::time::Duration) -> Result<(), anyhow::Error> {
+        if self.lock.is_none() {
+            let lock = super::lock::Lock::acquire(&self.path, timeout).await?;
+            self.lock = Some(std::sync::Arc::new(lock));
         }
+        Ok(())
+    }
+
+    /// Verify the signature of the database with `verifier` in `load`. Loading fails when the
+    /// database exists without the signature.
+    pub fn set_verifier(&mut self, verifier: super::signer::Verifier) {
+        self.verifier = Some(verifier);
+    }
+
+    /// Set compression of the archive written by `save`. Defaults to gzip with the default level.
+    pub fn set_compression(
+        &mut self,
+        compression: super::compression::Compression,
+        level: Option<u32>,
+    ) {
+        self.compression = compression;
+        self.compression_level = level;
+    }
+
+    pub fn compression(&self) -> super::compression::Compression {
+        self.compression
+    }
+
+    /// Whether `save` signs the database
+    pub fn has_signer(&self) -> bool {
+        self.signer.is_some()
     }
 
+    /// Path to the database, which is a symlink to `archive_path` after `save`
     pub fn path(&self) -> &std::path::Path {
         self.path.as_path()
     }
 
+    /// Path to the actual archive, e.g. foo.db.tar.gz for foo.db
+    pub fn archive_path(&self) -> std::path::PathBuf {
+        archive_path(&self.path, self.compression)
+    }
+
+    /// Iterate over entries in no particular order
+    pub fn entries(&self) -> impl Iterator<Item = &PackageEntry> {
+        self.entries.values()
+    }
+
+    pub fn get(&self, package_name: &str) -> Option<&PackageEntry> {
+        self.entries.get(package_name)
+    }
+
+    /// Entries whose pkgname or pkgdesc matches `pattern`
+    pub fn search<'r>(
+        &'r self,
+        pattern: &'r regex::Regex,
+    ) -> impl Iterator<Item = &'r PackageEntry> + 'r {
+        self.entries().filter(move |entry| {
+            pattern.is_match(&entry.desc.name) || pattern.is_match(&entry.desc.desc)
+        })
+    }
+
+    /// Entries belonging to `group`
+    pub fn by_group<'r>(&'r self, group: &'r str) -> impl Iterator<Item = &'r PackageEntry> + 'r {
+        self.entries()
+            .filter(move |entry| entry.desc.groups.iter().any(|g| g == group))
+    }
+
+    /// Entries providing `name`, with or without a version, e.g. `libfoo.so` for `libfoo.so=1-64`
+    pub fn by_provides<'r>(&'r self, name: &'r str) -> impl Iterator<Item = &'r PackageEntry> + 'r {
+        self.entries().filter(move |entry| {
+            entry.desc.provides.iter().any(|provide| {
+                provide == name || super::depend::Depend::parse(provide).name() == name
+            })
+        })
+    }
+
+    /// Entries satisfying `depend` by their names or provides
+    pub fn satisfiers<'r>(
+        &'r self,
+        depend: &'r super::depend::Depend,
+    ) -> impl Iterator<Item = &'r PackageEntry> + 'r {
+        self.entries()
+            .filter(move |entry| entry.desc.satisfies(depend))
+    }
+
+    /// Entries depending on or makedepending on `package_name`, either by the name or by one of
+    /// its provides such as `libfoo.so` of `libfoo.so=2-64`. Versions are ignored since a version
+    /// bump is what breaks them.
+    pub fn reverse_depends(&self, package_name: &str) -> Vec<&PackageEntry> {
+        let mut names = std::collections::HashSet::new();
+        names.insert(package_name.to_owned());
+        if let Some(entry) = self.get(package_name) {
+            for provide in entry.desc.parsed_provides() {
+                names.insert(provide.name().to_owned());
+            }
+        }
+        let mut entries: Vec<_> = self
+            .entries()
+            .filter(|entry| entry.desc.pkgname() != package_name)
+            .filter(|entry| {
+                entry
+                    .desc
+                    .parsed_depends()
+                    .iter()
+                    .chain(&entry.desc.parsed_makedepends())
+                    .any(|depend| names.contains(depend.name()))
+            })
+            .collect();
+        entries.sort_by(|a, b| a.desc.pkgname().cmp(b.desc.pkgname()));
+        entries
+    }
+
+    /// Pkgbases to be rebuilt after `package_name` is updated, i.e. transitive reverse
+    /// dependencies, in an order where each pkgbase comes after the ones it depends on.
+    /// Dependency cycles are broken in alphabetical order.
+    pub fn rebuild_list(&self, package_name: &str) -> Vec<String> {
+        let base_of = |entry: &PackageEntry| {
+            if entry.desc.pkgbase().is_empty() {
+                entry.desc.pkgname().to_owned()
+            } else {
+                entry.desc.pkgbase().to_owned()
+            }
+        };
+        let excluded_base = self.get(package_name).map(base_of);
+
+        // Collect transitive reverse dependencies
+        let mut affected = std::collections::BTreeMap::new();
+        let mut queue = vec![package_name.to_owned()];
+        while let Some(name) = queue.pop() {
+            for entry in self.reverse_depends(&name) {
+                if Some(base_of(entry)) != excluded_base
+                    && affected.insert(entry.desc.pkgname(), entry).is_none()
+                {
+                    queue.push(entry.desc.pkgname().to_owned());
+                }
+            }
+        }
+
+        // Edges from a pkgbase to the pkgbases depending on it
+        let provided_names = |entry: &PackageEntry| {
+            let mut names = vec![entry.desc.pkgname().to_owned()];
+            for provide in entry.desc.parsed_provides() {
+                names.push(provide.name().to_owned());
+            }
+            names
+        };
+        let mut dependents: std::collections::BTreeMap<String, std::collections::BTreeSet<String>> =
+            std::collections::BTreeMap::new();
+        let mut in_degrees: std::collections::BTreeMap<String, usize> =
+            std::collections::BTreeMap::new();
+        for entry in affected.values() {
+            in_degrees.entry(base_of(entry)).or_insert(0);
+        }
+        for entry in affected.values() {
+            let base = base_of(entry);
+            let depend_names: std::collections::HashSet<_> = entry
+                .desc
+                .parsed_depends()
+                .iter()
+                .chain(&entry.desc.parsed_makedepends())
+                .map(|depend| depend.name().to_owned())
+                .collect();
+            for other in affected.values() {
+                let other_base = base_of(other);
+                if other_base != base
+                    && provided_names(other)
+                        .iter()
+                        .any(|name| depend_names.contains(name))
+                    && dependents
+                        .entry(other_base)
+                        .or_default()
+                        .insert(base.clone())
+                {
+                    *in_degrees.entry(base.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut order = vec![];
+        while !in_degrees.is_empty() {
+            let next = in_degrees
+                .iter()
+                .find(|(_, degree)| **degree == 0)
+                .map(|(base, _)| base.clone())
+                .unwrap_or_else(|| {
+                    let base = in_degrees.keys().next().unwrap().clone();
+                    tracing::warn!("Dependency cycle found around {}", base);
+                    base
+                });
+            in_degrees.remove(&next);
+            for dependent in dependents.get(&next).into_iter().flatten() {
+                if let Some(degree) = in_degrees.get_mut(dependent) {
+                    *degree = degree.saturating_sub(1);
+                }
+            }
+            order.push(next);
+        }
+        order
+    }
+
+    /// Entries whose packager is `packager`, or contains `packager` as a part like an email address
+    pub fn by_packager<'r>(
+        &'r self,
+        packager: &'r str,
+    ) -> impl Iterator<Item = &'r PackageEntry> + 'r {
+        self.entries()
+            .filter(move |entry| entry.desc.packager.contains(packager))
+    }
+
+    /// Files matching `query` along with the entries shipping them.
+    /// Only databases saved with files, such as foo.files, have file lists.
+    pub fn search_files(&self, query: FileQuery) -> Vec<(&PackageEntry, &std::path::Path)> {
+        let mut matches = vec![];
+        for entry in self.entries() {
+            for file in &entry.files {
+                if query.matches(file) {
+                    matches.push((entry, file.as_path()));
+                }
+            }
+        }
+        matches
+    }
+
     pub fn load(&mut self) -> Result<(), anyhow::Error> {
         match std::fs::File::open(&self.path) {
-            Ok(file) => self.load_from_file(file),
+            Ok(file) => {
+                if let Some(ref verifier) = self.verifier {
+                    let mut sig_path = self.path.clone().into_os_string();
+                    sig_path.push(".sig");
+                    let sig_path = std::path::PathBuf::from(sig_path);
+                    if !sig_path.exists() {
+                        return Err(anyhow::anyhow!(
+                            "Signature {} not found",
+                            sig_path.display()
+                        ));
+                    }
+                    verifier.verify(&self.path, &sig_path).with_context(|| {
+                        format!("Unable to verify signature of {}", self.path.display())
+                    })?;
+                }
+                self.load_from_file(file)
+            }
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::NotFound {
                     Ok(())
@@ -72,8 +481,8 @@ impl<'a> Repository<'a> {
     }
 
     fn load_from_file(&mut self, file: std::fs::File) -> Result<(), anyhow::Error> {
-        let gz_reader = flate2::read::GzDecoder::new(file);
-        let mut tar_reader = tar::Archive::new(gz_reader);
+        let reader = super::compression::decompress(file)?;
+        let mut tar_reader = tar::Archive::new(reader);
         let mut desc_entries = std::collections::HashMap::new();
         let mut files_entries = std::collections::HashMap::new();
         for entry_result in tar_reader.entries()? {
@@ -114,15 +523,46 @@ impl<'a> Repository<'a> {
             }
         }
 
-        for (_, desc) in desc_entries {
-            let files = files_entries.remove(&desc.name).unwrap_or_default();
+        for (dirname, desc) in desc_entries {
+            let files = files_entries.remove(&dirname).unwrap_or_default();
             self.entries
                 .insert(desc.name.to_owned(), PackageEntry { desc, files });
         }
         Ok(())
     }
 
-    pub fn add(&mut self, package: &super::package::Package) {
+    /// Add `package` to the repository, replacing the entry of the same pkgname
+    pub fn add(
+        &mut self,
+        package: &super::package::Package,
+        mode: AddMode,
+    ) -> Result<AddResult, anyhow::Error> {
+        if let Some(entry) = self.entries.get(package.pkgname()) {
+            let current = super::version::Version::parse(&entry.desc.version);
+            let new = package.version();
+            match mode {
+                AddMode::Default if new < current => {
+                    return Err(anyhow::anyhow!(
+                        "Refusing to downgrade {} from {} to {}",
+                        package.pkgname(),
+                        current,
+                        new
+                    ));
+                }
+                AddMode::NewerOnly if new <= current => {
+                    tracing::warn!(
+                        "Skip {} {} since {} is already in {}",
+                        package.pkgname(),
+                        new,
+                        current,
+                        self.path.display()
+                    );
+                    return Ok(AddResult::Skipped);
+                }
+                _ => {}
+            }
+        }
+
         let desc = Desc {
             groups: package.groups().to_owned(),
             license: package.license().to_owned(),
@@ -148,26 +588,115 @@ impl<'a> Repository<'a> {
             makedepends: package.makedepends().to_owned(),
             checkdepends: package.checkdepends().to_owned(),
             optdepends: package.optdepends().to_owned(),
+            xdata: package.xdata().to_owned(),
+            extra_entries: group_extra_entries(package.extra_entries()),
         };
-        self.entries.insert(
-            desc.name.to_owned(),
-            PackageEntry {
-                desc,
-                files: package.files().to_owned(),
-            },
-        );
+        let displaced = self
+            .entries
+            .insert(
+                desc.name.to_owned(),
+                PackageEntry {
+                    desc,
+                    files: package.files().to_owned(),
+                },
+            )
+            .map(|old| old.desc.filename)
+            .filter(|filename| filename != package.filename());
+        Ok(AddResult::Added(displaced))
     }
 
-    pub fn remove(&mut self, package_name: &str) {
-        self.entries.remove(package_name);
+    /// Remove `package_name` from the repository and return the package filename of the entry
+    pub fn remove(&mut self, package_name: &str) -> Option<std::ffi::OsString> {
+        self.entries
+            .remove(package_name)
+            .map(|entry| entry.desc.filename)
+    }
+
+    /// Replace PGPSIG of `package_name` with the detached `signature`
+    pub fn set_signature(
+        &mut self,
+        package_name: &str,
+        signature: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        use base64::Engine as _;
+
+        let path = &self.path;
+        let entry = self
+            .entries
+            .get_mut(package_name)
+            .ok_or_else(|| anyhow::anyhow!("{} is not in {}", package_name, path.display()))?;
+        entry.desc.pgpsig = base64::engine::general_purpose::STANDARD.encode(signature);
+        Ok(())
     }
 
     pub async fn save(&self, include_files: bool) -> Result<(), anyhow::Error> {
-        let mut tmp_path = self.path.clone().into_os_string();
-        tmp_path.push(".progress");
-        let file = std::fs::File::create(&tmp_path)?;
-        let gz_writer = flate2::write::GzEncoder::new(file, flate2::Compression::default());
-        let mut builder = tar::Builder::new(gz_writer);
+        let staged = self.stage(include_files).await?;
+        let mut rollback = Rollback::default();
+        match self.commit(&staged, &mut rollback) {
+            Ok(()) => {
+                rollback.finish();
+                Ok(())
+            }
+            Err(e) => {
+                rollback.undo();
+                staged.discard();
+                Err(e)
+            }
+        }
+    }
+
+    /// Write the archive and its signature next to their final paths without replacing them
+    async fn stage(&self, include_files: bool) -> Result<Staged, anyhow::Error> {
+        let archive_path = self.archive_path();
+        let mut archive_sig_path = archive_path.clone().into_os_string();
+        archive_sig_path.push(".sig");
+        let staged = Staged {
+            archive_path,
+            archive_sig_path: std::path::PathBuf::from(archive_sig_path),
+        };
+        let result = async {
+            self.write_archive(&staged.progress_path(), include_files)?;
+            if let Some(signer) = &self.signer {
+                signer
+                    .sign(&staged.progress_path(), &staged.sig_progress_path())
+                    .await?;
+            }
+            Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => Ok(staged),
+            Err(e) => {
+                staged.discard();
+                Err(e)
+            }
+        }
+    }
+
+    /// Move the staged archive and signature into place and point the symlinks at them,
+    /// recording how to restore the previous ones in `rollback`
+    fn commit(&self, staged: &Staged, rollback: &mut Rollback) -> Result<(), anyhow::Error> {
+        if self.signer.is_some() {
+            rollback.rename(&staged.sig_progress_path(), &staged.archive_sig_path)?;
+        }
+        rollback.rename(&staged.progress_path(), &staged.archive_path)?;
+        rollback.symlink(&staged.archive_path, &self.path)?;
+        if self.signer.is_some() {
+            let mut sig_path = self.path.clone().into_os_string();
+            sig_path.push(".sig");
+            rollback.symlink(&staged.archive_sig_path, std::path::Path::new(&sig_path))?;
+        }
+        Ok(())
+    }
+
+    fn write_archive(
+        &self,
+        path: &std::path::Path,
+        include_files: bool,
+    ) -> Result<(), anyhow::Error> {
+        let file = std::fs::File::create(path)?;
+        let writer = super::compression::compress(file, self.compression, self.compression_level)?;
+        let mut builder = tar::Builder::new(writer);
         for package_entry in self.entries.values() {
             let pathbuf = std::path::PathBuf::from(format!(
                 "{}-{}/",
@@ -205,18 +734,411 @@ impl<'a> Repository<'a> {
                 builder.append(&files_header, files_bytes)?;
             }
         }
-        let gz_writer = builder.into_inner()?;
-        gz_writer.finish()?;
+        let writer = builder.into_inner()?;
+        writer.finish()?;
+        Ok(())
+    }
+}
 
-        if let Some(signer) = &self.signer {
-            let mut sig_path = self.path.clone().into_os_string();
-            sig_path.push(".sig");
-            signer.sign(&tmp_path, sig_path).await?;
+/// Archive and signature written by `Repository::stage` to `*.progress` paths
+struct Staged {
+    archive_path: std::path::PathBuf,
+    archive_sig_path: std::path::PathBuf,
+}
+
+impl Staged {
+    fn progress_path(&self) -> std::path::PathBuf {
+        progress_path(&self.archive_path)
+    }
+
+    fn sig_progress_path(&self) -> std::path::PathBuf {
+        progress_path(&self.archive_sig_path)
+    }
+
+    fn discard(&self) {
+        for path in [self.progress_path(), self.sig_progress_path()] {
+            if let Err(e) = std::fs::remove_file(&path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("Unable to remove {}: {}", path.display(), e);
+                }
+            }
         }
+    }
+}
+
+/// Previous archives, signatures and symlinks replaced by `Repository::commit`
+#[derive(Default)]
+struct Rollback {
+    undo: Vec<Undo>,
+}
+
+enum Undo {
+    // Move the backup of the previous file back
+    Restore {
+        backup: std::path::PathBuf,
+        path: std::path::PathBuf,
+    },
+    // Remove the file that did not exist before
+    Remove(std::path::PathBuf),
+    // Point the symlink at the previous target, or remove it if there was none
+    Relink {
+        link: std::path::PathBuf,
+        target: Option<std::path::PathBuf>,
+    },
+}
 
-        std::fs::rename(&tmp_path, &self.path)?;
+impl Rollback {
+    /// Replace `path` with `from`, keeping a hard link to the previous file
+    fn rename(
+        &mut self,
+        from: &std::path::Path,
+        path: &std::path::Path,
+    ) -> Result<(), anyhow::Error> {
+        if path.exists() {
+            let backup = rollback_path(path);
+            remove_file_if_exists(&backup)?;
+            std::fs::hard_link(path, &backup)?;
+            self.undo.push(Undo::Restore {
+                backup,
+                path: path.to_owned(),
+            });
+        } else {
+            self.undo.push(Undo::Remove(path.to_owned()));
+        }
+        std::fs::rename(from, path)?;
         Ok(())
     }
+
+    fn symlink(
+        &mut self,
+        target: &std::path::Path,
+        link: &std::path::Path,
+    ) -> Result<(), anyhow::Error> {
+        self.undo.push(Undo::Relink {
+            link: link.to_owned(),
+            target: std::fs::read_link(link).ok(),
+        });
+        symlink(target, link)
+    }
+
+    /// Keep the new files and remove the backups
+    fn finish(self) {
+        for undo in self.undo {
+            if let Undo::Restore { backup, .. } = undo {
+                if let Err(e) = remove_file_if_exists(&backup) {
+                    tracing::warn!("Unable to remove {}: {}", backup.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Restore the previous files in the reverse order
+    fn undo(self) {
+        for undo in self.undo.into_iter().rev() {
+            let result = match &undo {
+                Undo::Restore { backup, path } => {
+                    std::fs::rename(backup, path).map_err(anyhow::Error::from)
+                }
+                Undo::Remove(path) => remove_file_if_exists(path),
+                Undo::Relink {
+                    link,
+                    target: Some(target),
+                } => symlink(target, link),
+                Undo::Relink { link, target: None } => remove_file_if_exists(link),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Unable to restore the previous database: {}", e);
+            }
+        }
+    }
+}
+
+fn rollback_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(".rollback");
+    std::path::PathBuf::from(path)
+}
+
+fn remove_file_if_exists(path: &std::path::Path) -> Result<(), anyhow::Error> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(anyhow::Error::from(e)),
+    }
+}
+
+fn progress_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(".progress");
+    std::path::PathBuf::from(path)
+}
+
+/// Database and files database of one repository, e.g. foo.db and foo.files, updated together
+#[derive(Clone)]
+pub struct RepositoryPair<'a> {
+    db: Repository<'a>,
+    files: Repository<'a>,
+}
+
+impl<'a> RepositoryPair<'a> {
+    pub fn new(
+        db_path: std::path::PathBuf,
+        files_path: std::path::PathBuf,
+        signer: Option<&'a dyn super::signer::Signer>,
+    ) -> RepositoryPair<'a> {
+        RepositoryPair {
+            db: Repository::new(db_path, signer),
+            files: Repository::new(files_path, signer),
+        }
+    }
+
+    /// Pair foo.db with foo.files in the same directory. `db_path` can also be the archive like
+    /// foo.db.tar.gz, in which case its compression is used unless `set_compression` is called.
+    pub fn from_db_path(
+        db_path: std::path::PathBuf,
+        signer: Option<&'a dyn super::signer::Signer>,
+    ) -> Result<RepositoryPair<'a>, anyhow::Error> {
+        let (db_path, compression) = match split_archive_path(&db_path) {
+            Some((path, compression)) => (path, Some(compression)),
+            None => (db_path, None),
+        };
+        let files_path = files_path(&db_path).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unable to find files database for {} since it doesn't end with .db or .db.tar.*",
+                db_path.display()
+            )
+        })?;
+        let mut pair = Self::new(db_path, files_path, signer);
+        if let Some(compression) = compression {
+            pair.set_compression(compression, None);
+        }
+        Ok(pair)
+    }
+
+    pub fn set_compression(
+        &mut self,
+        compression: super::compression::Compression,
+        level: Option<u32>,
+    ) {
+        self.db.set_compression(compression, level);
+        self.files.set_compression(compression, level);
+    }
+
+    pub fn compression(&self) -> super::compression::Compression {
+        self.db.compression()
+    }
+
+    pub fn db(&self) -> &Repository<'a> {
+        &self.db
+    }
+
+    pub fn files(&self) -> &Repository<'a> {
+        &self.files
+    }
+
+    /// Take the lock shared by both databases. See `Repository::lock`.
+    pub async fn lock(&mut self, timeout: std::time::Duration) -> Result<(), anyhow::Error> {
+        self.db.lock(timeout).await
+    }
+
+    pub fn load(&mut self) -> Result<(), anyhow::Error> {
+        self.db.load().with_context(|| {
+            format!(
+                "Unable to load database repository from {}",
+                self.db.path().display()
+            )
+        })?;
+        self.files.load().with_context(|| {
+            format!(
+                "Unable to load files repository from {}",
+                self.files.path().display()
+            )
+        })
+    }
+
+    /// Verify the signatures of both databases in `load`
+    pub fn set_verifier(&mut self, verifier: super::signer::Verifier) {
+        self.files.set_verifier(verifier.clone());
+        self.db.set_verifier(verifier);
+    }
+
+    /// Add `package` to both databases. `mode` is decided by the entry in the database.
+    pub fn add(
+        &mut self,
+        package: &super::package::Package,
+        mode: AddMode,
+    ) -> Result<AddResult, anyhow::Error> {
+        let result = self.db.add(package, mode)?;
+        if let AddResult::Added(_) = result {
+            self.files.add(package, AddMode::Force)?;
+        }
+        Ok(result)
+    }
+
+    /// Remove `package_name` from both databases and return the package filename of the entry
+    pub fn remove(&mut self, package_name: &str) -> Option<std::ffi::OsString> {
+        let files_filename = self.files.remove(package_name);
+        self.db.remove(package_name).or(files_filename)
+    }
+
+    /// Replace PGPSIG of `package_name` in both databases
+    pub fn set_signature(
+        &mut self,
+        package_name: &str,
+        signature: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        self.db.set_signature(package_name, signature)?;
+        self.files.set_signature(package_name, signature)
+    }
+
+    /// Save both databases along with their signatures, all or nothing. Nothing is replaced
+    /// unless both archives are written and signed, and the previous archives, signatures and
+    /// symlinks of both databases are restored when replacing any of them fails.
+    pub async fn save(&self) -> Result<(), anyhow::Error> {
+        let db_staged = self.db.stage(false).await.with_context(|| {
+            format!(
+                "Unable to save database repository to {}",
+                self.db.path().display()
+            )
+        })?;
+        let files_staged = match self.files.stage(true).await {
+            Ok(staged) => staged,
+            Err(e) => {
+                db_staged.discard();
+                return Err(e.context(format!(
+                    "Unable to save files repository to {}",
+                    self.files.path().display()
+                )));
+            }
+        };
+        let mut rollback = Rollback::default();
+        let result = self
+            .db
+            .commit(&db_staged, &mut rollback)
+            .and_then(|()| self.files.commit(&files_staged, &mut rollback));
+        match result {
+            Ok(()) => {
+                rollback.finish();
+                Ok(())
+            }
+            Err(e) => {
+                rollback.undo();
+                db_staged.discard();
+                files_staged.discard();
+                Err(e.context(format!(
+                    "Unable to replace {} and {}",
+                    self.db.path().display(),
+                    self.files.path().display()
+                )))
+            }
+        }
+    }
+}
+
+/// foo.files for foo.db, or foo.files.tar.gz for foo.db.tar.gz
+pub fn files_path<P>(db_path: P) -> Option<std::path::PathBuf>
+where
+    P: AsRef<std::path::Path>,
+{
+    let db_path = db_path.as_ref();
+    let filename = db_path.file_name()?.to_str()?;
+    if let Some(prefix) = filename.strip_suffix(".db") {
+        return Some(db_path.with_file_name(format!("{}.files", prefix)));
+    }
+    let (path, compression) = split_archive_path(db_path)?;
+    Some(archive_path(files_path(path)?, compression))
+}
+
+// Split foo.db.tar.gz into foo.db and its compression
+fn split_archive_path(
+    path: &std::path::Path,
+) -> Option<(std::path::PathBuf, super::compression::Compression)> {
+    let filename = path.file_name()?.to_str()?;
+    let (prefix, tar_extension) = filename.rsplit_once(".db.")?;
+    let compression = match tar_extension.strip_prefix("tar")? {
+        "" => super::compression::Compression::None,
+        extension => extension.strip_prefix('.')?.parse().ok()?,
+    };
+    if compression.tar_extension() != tar_extension {
+        return None;
+    }
+    Some((path.with_file_name(format!("{}.db", prefix)), compression))
+}
+
+/// Select package files of `pkgname` from `filenames` to be deleted, retaining the newest `keep`
+/// of them. Files not named like `pkgname-pkgver-pkgrel-arch.pkg.tar.*` are ignored.
+pub fn stale_package_files<S>(filenames: &[S], pkgname: &str, keep: usize) -> Vec<S>
+where
+    S: AsRef<std::ffi::OsStr> + Clone,
+{
+    let mut packages: Vec<_> = filenames
+        .iter()
+        .filter_map(|filename| {
+            let (name, version) = parse_package_filename(filename.as_ref().to_str()?)?;
+            if name == pkgname {
+                Some((version, filename.clone()))
+            } else {
+                None
+            }
+        })
+        .collect();
+    packages.sort_by(|(a, _), (b, _)| b.cmp(a));
+    packages
+        .into_iter()
+        .skip(keep)
+        .map(|(_, filename)| filename)
+        .collect()
+}
+
+// Split pkgname-pkgver-pkgrel-arch.pkg.tar.* into pkgname and version
+fn parse_package_filename(filename: &str) -> Option<(&str, super::version::Version)> {
+    let stem = &filename[..filename.find(".pkg.tar")?];
+    if filename.ends_with(".sig") {
+        return None;
+    }
+    let mut rsplitn = stem.rsplitn(4, '-');
+    let _arch = rsplitn.next()?;
+    let pkgrel = rsplitn.next()?;
+    let pkgver = rsplitn.next()?;
+    let pkgname = rsplitn.next()?;
+    Some((
+        pkgname,
+        super::version::Version::parse(&format!("{}-{}", pkgver, pkgrel)),
+    ))
+}
+
+/// Path to the archive of the database at `path` compressed with `compression`,
+/// following the naming of repo-add
+pub fn archive_path<P>(path: P, compression: super::compression::Compression) -> std::path::PathBuf
+where
+    P: AsRef<std::path::Path>,
+{
+    let mut archive_path = path.as_ref().as_os_str().to_os_string();
+    archive_path.push(".");
+    archive_path.push(compression.tar_extension());
+    std::path::PathBuf::from(archive_path)
+}
+
+// Atomically replace `link` with a relative symlink to `target`, which must be in the same directory
+fn symlink<P, Q>(target: P, link: Q) -> Result<(), anyhow::Error>
+where
+    P: AsRef<std::path::Path>,
+    Q: AsRef<std::path::Path>,
+{
+    let link = link.as_ref();
+    let target = target
+        .as_ref()
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid symlink target"))?;
+    let mut tmp_link = link.as_os_str().to_os_string();
+    tmp_link.push(".progress");
+    remove_file_if_exists(std::path::Path::new(&tmp_link))?;
+    std::os::unix::fs::symlink(target, &tmp_link)?;
+    if let Err(e) = std::fs::rename(&tmp_link, link) {
+        let _ = std::fs::remove_file(&tmp_link);
+        return Err(anyhow::Error::from(e));
+    }
+    Ok(())
 }
 
 fn parse_desc(body: &str) -> Result<Desc, anyhow::Error> {
@@ -292,9 +1214,17 @@ fn parse_desc(body: &str) -> Result<Desc, anyhow::Error> {
             "OPTDEPENDS" => {
                 desc.optdepends.push(val.to_owned());
             }
-            _ => {
-                return Err(anyhow::anyhow!("Unknown desc entry: {}", key));
+            "XDATA" => {
+                desc.xdata.push(val.to_owned());
             }
+            _ => match desc.extra_entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, vals)) => vals.push(val.to_owned()),
+                None => {
+                    tracing::warn!("Unknown desc entry: {}", key);
+                    desc.extra_entries
+                        .push((key.to_owned(), vec![val.to_owned()]));
+                }
+            },
         }
     }
     Ok(desc)
@@ -323,7 +1253,7 @@ impl<'a> Iterator for EachEntry<'a> {
     }
 }
 
-fn each_entry(body: &str) -> EachEntry {
+fn each_entry(body: &str) -> EachEntry<'_> {
     EachEntry {
         key: "",
         lines: body.lines(),
@@ -356,9 +1286,26 @@ fn into_desc_file(package_entry: &PackageEntry) -> Vec<u8> {
     desc_write_array(&mut buf, b"MAKEDEPENDS", &desc.makedepends);
     desc_write_array(&mut buf, b"CHECKDEPENDS", &desc.checkdepends);
     desc_write_array(&mut buf, b"OPTDEPENDS", &desc.optdepends);
+    desc_write_array(&mut buf, b"XDATA", &desc.xdata);
+    for (key, vals) in &desc.extra_entries {
+        desc_write_array(&mut buf, key.as_bytes(), vals);
+    }
     buf
 }
 
+/// Group unknown .PKGINFO entries by desc key, e.g. `foo = bar` into `%FOO%`
+fn group_extra_entries(entries: &[(String, String)]) -> Vec<(String, Vec<String>)> {
+    let mut grouped: Vec<(String, Vec<String>)> = vec![];
+    for (key, val) in entries {
+        let key = key.to_uppercase();
+        match grouped.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, vals)) => vals.push(val.to_owned()),
+            None => grouped.push((key, vec![val.to_owned()])),
+        }
+    }
+    grouped
+}
+
 fn desc_write_array(buf: &mut Vec<u8>, key: &[u8], xs: &[String]) {
     if !xs.is_empty() {
         buf.extend_from_slice(b"%");
@@ -429,3 +1376,47 @@ fn into_files_file(files: &[std::path::PathBuf]) -> Vec<u8> {
     }
     buf
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RepositoryPair;
+
+    fn inode(path: &std::path::Path) -> u64 {
+        use std::os::unix::fs::MetadataExt as _;
+        std::fs::metadata(path).unwrap().ino()
+    }
+
+    #[tokio::test]
+    async fn test_save_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let pair = RepositoryPair::from_db_path(dir.path().join("foo.db"), None).unwrap();
+        pair.save().await.unwrap();
+        let db_archive = dir.path().join("foo.db.tar.gz");
+        let files_archive = dir.path().join("foo.files.tar.gz");
+        let db_inode = inode(&db_archive);
+        let files_inode = inode(&files_archive);
+
+        // Replacing the symlink of foo.files fails after foo.db is committed
+        let files_link = dir.path().join("foo.files");
+        std::fs::remove_file(&files_link).unwrap();
+        std::fs::create_dir(&files_link).unwrap();
+        std::fs::write(files_link.join("x"), b"").unwrap();
+        assert!(pair.save().await.is_err());
+
+        assert_eq!(inode(&db_archive), db_inode);
+        assert_eq!(inode(&files_archive), files_inode);
+        assert_eq!(
+            std::fs::read_link(dir.path().join("foo.db")).unwrap(),
+            std::path::Path::new("foo.db.tar.gz")
+        );
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["foo.db", "foo.db.tar.gz", "foo.files", "foo.files.tar.gz"]
+        );
+    }
+}
@@ -1,28 +1,995 @@
-#[derive(Debug, Clone, Copy)]
-pub struct Signer<'a> {
-    key: &'a str,
+/// Backend creating detached signatures of packages and repository databases
+pub trait Signer: std::fmt::Debug + Send + Sync {
+    /// Write the binary detached signature of the file at `path` to `sig_path`
+    fn sign<'s>(
+        &'s self,
+        path: &'s std::path::Path,
+        sig_path: &'s std::path::Path,
+    ) -> futures::future::BoxFuture<'s, Result<(), anyhow::Error>>;
 }
 
-impl<'a> Signer<'a> {
-    pub fn new(key: &'a str) -> Signer {
-        Signer { key }
+/// Selects a `Signer` implementation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+pub enum SignerBackend {
+    /// gpgme with the user's keyring, where keys are key IDs or fingerprints
+    #[default]
+    #[serde(rename = "gpgme")]
+    Gpgme,
+    /// Pure Rust OpenPGP implementation, where keys are paths to secret key files
+    #[serde(rename = "openpgp")]
+    OpenPgp,
+    /// External command, which is given keys in GUZUTA_SIGN_KEY
+    #[serde(rename = "command")]
+    Command,
+}
+
+impl std::fmt::Display for SignerBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            SignerBackend::Gpgme => "gpgme".fmt(f),
+            SignerBackend::OpenPgp => "openpgp".fmt(f),
+            SignerBackend::Command => "command".fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for SignerBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gpgme" => Ok(Self::Gpgme),
+            "openpgp" => Ok(Self::OpenPgp),
+            "command" => Ok(Self::Command),
+            _ => anyhow::bail!("Unknown signer backend: {s}"),
+        }
+    }
+}
+
+/// Where to read a secret key or a passphrase from, written as `file:PATH`, `env:NAME` or
+/// `command:COMMAND`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum SecretSource {
+    File(std::path::PathBuf),
+    Env(String),
+    /// Shell command printing the secret to stdout
+    Command(String),
+}
+
+impl SecretSource {
+    pub fn read(&self) -> Result<Vec<u8>, anyhow::Error> {
+        use anyhow::Context as _;
+
+        match *self {
+            SecretSource::File(ref path) => {
+                std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))
+            }
+            SecretSource::Env(ref name) => std::env::var_os(name)
+                .map(|val| {
+                    use std::os::unix::ffi::OsStringExt as _;
+                    val.into_vec()
+                })
+                .ok_or_else(|| anyhow::anyhow!("Environment variable {} is not set", name)),
+            SecretSource::Command(ref command) => {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stderr(std::process::Stdio::inherit())
+                    .output()
+                    .with_context(|| format!("Unable to execute {}", command))?;
+                if output.status.success() {
+                    Ok(output.stdout)
+                } else {
+                    Err(anyhow::anyhow!(
+                        "Command failed with {}: {}",
+                        output.status,
+                        command
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Read a passphrase without the trailing newline
+    fn read_passphrase(&self) -> Result<Passphrase, anyhow::Error> {
+        let mut buf = String::from_utf8(self.read()?)
+            .map_err(|_| anyhow::anyhow!("Passphrase from {} is not UTF-8", self))?;
+        let len = buf.trim_end_matches(&['\r', '\n'][..]).len();
+        buf.truncate(len);
+        Ok(Passphrase(buf))
+    }
+}
+
+impl std::fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            SecretSource::File(ref path) => write!(f, "file:{}", path.display()),
+            SecretSource::Env(ref name) => write!(f, "env:{}", name),
+            SecretSource::Command(ref command) => write!(f, "command:{}", command),
+        }
+    }
+}
+
+impl std::str::FromStr for SecretSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("file", path)) => Ok(Self::File(std::path::PathBuf::from(path))),
+            Some(("env", name)) => Ok(Self::Env(name.to_owned())),
+            Some(("command", command)) => Ok(Self::Command(command.to_owned())),
+            _ => anyhow::bail!("file:PATH, env:NAME or command:COMMAND is expected: {s}"),
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for SecretSource {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Passphrase hidden from Debug
+#[derive(Clone)]
+struct Passphrase(String);
+
+impl std::fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str("Passphrase(..)")
+    }
+}
+
+/// Signing configuration shared by package and repository keys
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct SignerConfig {
+    #[serde(default)]
+    pub backend: SignerBackend,
+    /// Shell command of the command backend
+    pub command: Option<String>,
+    /// Armored secret key imported into a temporary GNUPGHOME by the gpgme backend instead of
+    /// using the user's keyring
+    pub secret_key: Option<SecretSource>,
+    /// Passphrase of the secret key for the gpgme and openpgp backends
+    pub passphrase: Option<SecretSource>,
+}
+
+impl SignerConfig {
+    /// Create a signer with `key` for the backend
+    pub fn signer(&self, key: &str) -> Result<Box<dyn Signer>, anyhow::Error> {
+        use anyhow::Context as _;
+
+        if self.secret_key.is_some() && self.backend != SignerBackend::Gpgme {
+            anyhow::bail!("secret_key is supported only by gpgme backend");
+        }
+        let passphrase = self
+            .passphrase
+            .as_ref()
+            .map(|source| {
+                source
+                    .read_passphrase()
+                    .with_context(|| format!("Unable to read passphrase from {}", source))
+            })
+            .transpose()?;
+        Ok(match self.backend {
+            SignerBackend::Gpgme => {
+                let mut signer = match self.secret_key {
+                    Some(ref source) => {
+                        let secret_key = source.read().with_context(|| {
+                            format!("Unable to read secret key from {}", source)
+                        })?;
+                        GpgmeSigner::with_secret_key(key, &secret_key).with_context(|| {
+                            format!("Unable to import secret key from {}", source)
+                        })?
+                    }
+                    None => GpgmeSigner::new(key),
+                };
+                signer.passphrase = passphrase;
+                signer.check()?;
+                Box::new(signer)
+            }
+            SignerBackend::OpenPgp => {
+                let mut signer = OpenPgpSigner::load(key)?;
+                signer.passphrase = passphrase;
+                Box::new(signer)
+            }
+            SignerBackend::Command => Box::new(CommandSigner::new(
+                self.command
+                    .as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("command is required for command backend"))?,
+                key,
+            )),
+        })
+    }
+
+    /// Create a signer combining signatures by all of `keys`, or None if `keys` is empty
+    pub fn combined_signer<S>(&self, keys: &[S]) -> Result<Option<Box<dyn Signer>>, anyhow::Error>
+    where
+        S: AsRef<str>,
+    {
+        let mut signers = keys
+            .iter()
+            .map(|key| self.signer(key.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(match signers.len() {
+            0 => None,
+            1 => signers.pop(),
+            _ => Some(Box::new(MultiSigner::new(signers))),
+        })
+    }
+}
+
+/// Signs with all of the signers and concatenates their signatures into one file, which pacman
+/// accepts as multiple signatures
+#[derive(Debug)]
+pub struct MultiSigner {
+    signers: Vec<Box<dyn Signer>>,
+}
+
+impl MultiSigner {
+    pub fn new(signers: Vec<Box<dyn Signer>>) -> MultiSigner {
+        MultiSigner { signers }
+    }
+}
+
+impl Signer for MultiSigner {
+    fn sign<'s>(
+        &'s self,
+        path: &'s std::path::Path,
+        sig_path: &'s std::path::Path,
+    ) -> futures::future::BoxFuture<'s, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let mut signatures = vec![];
+            for (i, signer) in self.signers.iter().enumerate() {
+                let mut part_path = sig_path.as_os_str().to_os_string();
+                part_path.push(format!(".{}", i));
+                let part_path = std::path::PathBuf::from(part_path);
+                let result = match signer.sign(path, &part_path).await {
+                    Ok(()) => std::fs::read(&part_path).map_err(anyhow::Error::from),
+                    Err(e) => Err(e),
+                };
+                let _ = std::fs::remove_file(&part_path);
+                signatures.extend(result?);
+            }
+            std::fs::write(sig_path, signatures)?;
+            Ok(())
+        })
+    }
+}
+
+/// Verification of signatures with `Verifier`
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct VerifyConfig {
+    /// Fingerprints of keys expected to sign. Any key is accepted if empty.
+    #[serde(default)]
+    pub fingerprints: Vec<String>,
+    /// Path to a file of public keys used instead of the user's keyring
+    pub keyring: Option<std::path::PathBuf>,
+}
+
+impl VerifyConfig {
+    pub fn verifier(&self) -> Result<Verifier, anyhow::Error> {
+        let mut verifier = Verifier::new();
+        verifier.set_fingerprints(&self.fingerprints);
+        if let Some(ref keyring) = self.keyring {
+            verifier.load_keyring(keyring)?;
+        }
+        Ok(verifier)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GpgmeSigner {
+    key: String,
+    passphrase: Option<Passphrase>,
+    home: Option<std::sync::Arc<GnupgHome>>,
+}
+
+/// Temporary GNUPGHOME, whose gpg-agent is killed on drop
+#[derive(Debug)]
+struct GnupgHome {
+    dir: tempfile::TempDir,
+}
+
+impl Drop for GnupgHome {
+    fn drop(&mut self) {
+        let _ = std::process::Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(self.dir.path())
+            .args(["--kill", "gpg-agent"])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+    }
+}
+
+impl GpgmeSigner {
+    pub fn new(key: &str) -> GpgmeSigner {
+        GpgmeSigner {
+            key: key.to_owned(),
+            passphrase: None,
+            home: None,
+        }
     }
 
-    pub async fn sign<P, Q>(&self, path: P, sig_path: Q) -> Result<(), anyhow::Error>
+    /// Import `secret_key` into a temporary GNUPGHOME removed when the signer is dropped, so
+    /// that it works without the user's keyring, gpg-agent or pinentry
+    pub fn with_secret_key(key: &str, secret_key: &[u8]) -> Result<GpgmeSigner, anyhow::Error> {
+        let dir = tempfile::Builder::new().prefix("guzuta-gnupg.").tempdir()?;
+        let signer = GpgmeSigner {
+            key: key.to_owned(),
+            passphrase: None,
+            home: Some(std::sync::Arc::new(GnupgHome { dir })),
+        };
+        let mut ctx = signer.context()?;
+        let result = ctx.import(secret_key)?;
+        if result.secret_imported() == 0 && result.secret_unchanged() == 0 {
+            anyhow::bail!("No secret key found");
+        }
+        Ok(signer)
+    }
+
+    /// Supply `passphrase` by loopback pinentry instead of asking it via gpg-agent
+    pub fn set_passphrase(&mut self, passphrase: String) {
+        self.passphrase = Some(Passphrase(passphrase));
+    }
+
+    /// Check that the key is available and usable for signing
+    pub fn check(&self) -> Result<(), anyhow::Error> {
+        let mut ctx = self.context()?;
+        self.secret_key(&mut ctx)?;
+        Ok(())
+    }
+
+    fn context(&self) -> Result<gpgme::Context, anyhow::Error> {
+        let mut ctx = gpgme::Context::from_protocol(gpgme::Protocol::OpenPgp)?;
+        if let Some(ref home) = self.home {
+            use std::os::unix::ffi::OsStrExt as _;
+            ctx.set_engine_home_dir(home.dir.path().as_os_str().as_bytes())?;
+        }
+        // Never fall back to pinentry, which hangs without a terminal
+        if self.home.is_some() || self.passphrase.is_some() {
+            ctx.set_pinentry_mode(gpgme::PinentryMode::Loopback)?;
+        }
+        Ok(ctx)
+    }
+
+    fn secret_key(&self, ctx: &mut gpgme::Context) -> Result<gpgme::Key, anyhow::Error> {
+        use anyhow::Context as _;
+
+        let key = match ctx.get_secret_key(&self.key) {
+            Ok(key) => key,
+            Err(e) if e.code() == gpgme::Error::EOF.code() => {
+                anyhow::bail!(
+                    "Secret key {} not found in {}",
+                    self.key,
+                    self.home.as_ref().map_or_else(
+                        || "the keyring".to_owned(),
+                        |home| format!("the imported keyring {}", home.dir.path().display())
+                    )
+                );
+            }
+            Err(e) => {
+                return Err(anyhow::Error::from(e))
+                    .with_context(|| format!("Unable to get secret key {}", self.key))
+            }
+        };
+        if key.is_revoked() {
+            anyhow::bail!("Secret key {} is revoked", self.key);
+        }
+        if key.is_expired() {
+            anyhow::bail!("Secret key {} is expired", self.key);
+        }
+        let usable = key.subkeys().any(|subkey| {
+            subkey.can_sign()
+                && subkey.is_secret()
+                && !subkey.is_expired()
+                && !subkey.is_revoked()
+                && !subkey.is_invalid()
+                && !subkey.is_disabled()
+        });
+        if !usable {
+            anyhow::bail!(
+                "Secret key {} has no usable signing subkey, which may be expired",
+                self.key
+            );
+        }
+        Ok(key)
+    }
+}
+
+impl Signer for GpgmeSigner {
+    fn sign<'s>(
+        &'s self,
+        path: &'s std::path::Path,
+        sig_path: &'s std::path::Path,
+    ) -> futures::future::BoxFuture<'s, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            tokio::task::block_in_place(|| {
+                let mut ctx = self.context()?;
+                let key = self.secret_key(&mut ctx)?;
+                ctx.add_signer(&key)?;
+                let reader = std::fs::File::open(path)?;
+                let mut input = gpgme::Data::from_reader(reader)?;
+                let writer = std::fs::File::create(sig_path)?;
+                let mut output = gpgme::Data::from_writer(writer)?;
+                match self.passphrase {
+                    Some(ref passphrase) => ctx.with_passphrase_provider(
+                        |request: gpgme::PassphraseRequest<'_>, out: &mut dyn std::io::Write| {
+                            // Fail instead of being asked for the same wrong passphrase again
+                            if request.prev_attempt_failed {
+                                return Err(gpgme::Error::BAD_PASSPHRASE);
+                            }
+                            out.write_all(passphrase.0.as_bytes())?;
+                            out.write_all(b"\n")?;
+                            Ok(())
+                        },
+                        |ctx| ctx.sign(gpgme::SignMode::Detached, &mut input, &mut output),
+                    )?,
+                    None => ctx.sign(gpgme::SignMode::Detached, &mut input, &mut output)?,
+                };
+                Ok(())
+            })
+        })
+    }
+}
+
+/// Signs with a secret key file without gpg-agent
+#[derive(Debug, Clone)]
+pub struct OpenPgpSigner {
+    key: pgp::composed::SignedSecretKey,
+    passphrase: Option<Passphrase>,
+}
+
+impl OpenPgpSigner {
+    /// Load an armored or binary secret key. Protected keys require `set_passphrase`.
+    pub fn load<P>(path: P) -> Result<OpenPgpSigner, anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        use anyhow::Context as _;
+        use pgp::composed::Deserializable as _;
+
+        let path = path.as_ref();
+        let buf = std::fs::read(path)
+            .with_context(|| format!("Unable to read secret key {}", path.display()))?;
+        let key = if buf.starts_with(b"-----BEGIN") {
+            pgp::composed::SignedSecretKey::from_armor_single(buf.as_slice()).map(|(key, _)| key)
+        } else {
+            pgp::composed::SignedSecretKey::from_bytes(buf.as_slice())
+        }
+        .with_context(|| format!("Unable to parse secret key {}", path.display()))?;
+        Ok(OpenPgpSigner {
+            key,
+            passphrase: None,
+        })
+    }
+
+    pub fn set_passphrase(&mut self, passphrase: String) {
+        self.passphrase = Some(Passphrase(passphrase));
+    }
+}
+
+impl Signer for OpenPgpSigner {
+    fn sign<'s>(
+        &'s self,
+        path: &'s std::path::Path,
+        sig_path: &'s std::path::Path,
+    ) -> futures::future::BoxFuture<'s, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            tokio::task::block_in_place(|| {
+                use pgp::ser::Serialize as _;
+
+                let signature = match self.signing_subkey() {
+                    Some(subkey) => self.sign_with(
+                        subkey,
+                        subkey.secret_params().is_encrypted(),
+                        |password| subkey.unlock(password, |_, _| Ok(())),
+                        path,
+                    )?,
+                    None if can_sign(self.primary_key_signatures()) => {
+                        let primary_key = &self.key.primary_key;
+                        self.sign_with(
+                            primary_key,
+                            primary_key.secret_params().is_encrypted(),
+                            |password| primary_key.unlock(password, |_, _| Ok(())),
+                            path,
+                        )?
+                    }
+                    None => anyhow::bail!("Secret key has no key usable for signing"),
+                };
+                std::fs::write(sig_path, signature.to_bytes()?)?;
+                Ok(())
+            })
+        })
+    }
+}
+
+impl OpenPgpSigner {
+    // The newest subkey allowed to sign that is neither revoked nor expired
+    fn signing_subkey(&self) -> Option<&pgp::packet::SecretSubkey> {
+        use pgp::types::KeyDetails as _;
+
+        let now = pgp::types::Timestamp::now().as_secs() as u64;
+        self.key
+            .secret_subkeys
+            .iter()
+            .filter(|subkey| {
+                let revoked = subkey.signatures.iter().any(|signature| {
+                    signature.typ() == Some(pgp::packet::SignatureType::SubkeyRevocation)
+                });
+                let binding = subkey
+                    .signatures
+                    .iter()
+                    .filter(|signature| {
+                        signature.typ() == Some(pgp::packet::SignatureType::SubkeyBinding)
+                    })
+                    .max_by_key(|signature| signature.created().map(|t| t.as_secs()));
+                let expired = binding
+                    .and_then(|signature| signature.key_expiration_time())
+                    .map(|duration| duration.as_secs())
+                    .filter(|secs| *secs != 0)
+                    .is_some_and(|secs| {
+                        subkey.key.created_at().as_secs() as u64 + secs as u64 <= now
+                    });
+                !revoked && !expired && can_sign(binding)
+            })
+            .max_by_key(|subkey| subkey.key.created_at().as_secs())
+            .map(|subkey| &subkey.key)
+    }
+
+    // Self-signatures carrying key flags of the primary key
+    fn primary_key_signatures(&self) -> impl Iterator<Item = &pgp::packet::Signature> {
+        self.key.details.direct_signatures.iter().chain(
+            self.key
+                .details
+                .users
+                .iter()
+                .flat_map(|user| user.signatures.iter()),
+        )
+    }
+
+    fn sign_with<K, F>(
+        &self,
+        key: &K,
+        is_encrypted: bool,
+        unlock: F,
+        path: &std::path::Path,
+    ) -> Result<pgp::composed::DetachedSignature, anyhow::Error>
+    where
+        K: pgp::types::SigningKey,
+        F: FnOnce(&pgp::types::Password) -> pgp::errors::Result<pgp::errors::Result<()>>,
+    {
+        let password = match self.passphrase {
+            Some(ref passphrase) => pgp::types::Password::from(passphrase.0.as_str()),
+            None if is_encrypted => {
+                anyhow::bail!("Secret key is protected but no passphrase is given");
+            }
+            None => pgp::types::Password::empty(),
+        };
+        unlock(&password)
+            .map_err(|_| anyhow::anyhow!("Unable to unlock secret key with the passphrase"))??;
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(pgp::composed::DetachedSignature::sign_binary_data(
+            rand::thread_rng(),
+            key,
+            &password,
+            pgp::crypto::hash::HashAlgorithm::Sha256,
+            reader,
+        )?)
+    }
+}
+
+fn can_sign<'s, I>(signatures: I) -> bool
+where
+    I: IntoIterator<Item = &'s pgp::packet::Signature>,
+{
+    signatures
+        .into_iter()
+        .any(|signature| signature.key_flags().sign())
+}
+
+/// Runs a shell command reading the file from stdin and writing the signature to stdout, e.g.
+/// `gpg --detach-sign --local-user "$GUZUTA_SIGN_KEY"`. The path of the file is also given in
+/// GUZUTA_SIGN_FILE.
+#[derive(Debug, Clone)]
+pub struct CommandSigner {
+    command: String,
+    key: String,
+}
+
+impl CommandSigner {
+    pub fn new(command: &str, key: &str) -> CommandSigner {
+        CommandSigner {
+            command: command.to_owned(),
+            key: key.to_owned(),
+        }
+    }
+}
+
+impl Signer for CommandSigner {
+    fn sign<'s>(
+        &'s self,
+        path: &'s std::path::Path,
+        sig_path: &'s std::path::Path,
+    ) -> futures::future::BoxFuture<'s, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let mut cmd = tokio::process::Command::new("sh");
+            cmd.arg("-c")
+                .arg(&self.command)
+                .env("GUZUTA_SIGN_KEY", &self.key)
+                .env("GUZUTA_SIGN_FILE", path)
+                .stdin(std::fs::File::open(path)?)
+                .stdout(std::fs::File::create(sig_path)?);
+            tracing::info!("{:?}", cmd);
+            let status = cmd.status().await?;
+            if status.success() {
+                Ok(())
+            } else {
+                let _ = std::fs::remove_file(sig_path);
+                Err(anyhow::anyhow!(
+                    "Signing command failed with {}: {}",
+                    status,
+                    self.command
+                ))
+            }
+        })
+    }
+}
+
+/// Verifies detached signatures with gpgme and the user's keyring, or with public keys in a
+/// keyring file
+#[derive(Debug, Clone, Default)]
+pub struct Verifier {
+    fingerprints: Vec<String>,
+    keyring: Option<Vec<pgp::composed::SignedPublicKey>>,
+}
+
+impl Verifier {
+    pub fn new() -> Verifier {
+        Verifier::default()
+    }
+
+    /// Accept only signatures made by these keys. Fingerprints of primary keys and subkeys are
+    /// compared ignoring case and spaces.
+    pub fn set_fingerprints<S>(&mut self, fingerprints: &[S])
+    where
+        S: AsRef<str>,
+    {
+        self.fingerprints = fingerprints
+            .iter()
+            .map(|fingerprint| normalize_fingerprint(fingerprint.as_ref()))
+            .collect();
+    }
+
+    /// Whether signatures are accepted only from specific keys
+    pub fn has_fingerprints(&self) -> bool {
+        !self.fingerprints.is_empty()
+    }
+
+    /// Verify with armored or binary public keys in the file instead of the user's keyring
+    pub fn load_keyring<P>(&mut self, path: P) -> Result<(), anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        use anyhow::Context as _;
+
+        let path = path.as_ref();
+        let buf = std::fs::read(path)
+            .with_context(|| format!("Unable to read keyring {}", path.display()))?;
+        let keys = parse_many(&buf)
+            .with_context(|| format!("Unable to parse keyring {}", path.display()))?;
+        self.keyring = Some(keys);
+        Ok(())
+    }
+
+    /// Verify the detached signature at `sig_path` over the file at `path`. At least one
+    /// signature must be good and made by the expected keys, if any. Signatures by unknown keys
+    /// are skipped, but a bad signature fails even if another one is good.
+    pub fn verify<P, Q>(&self, path: P, sig_path: Q) -> Result<(), anyhow::Error>
     where
         P: AsRef<std::path::Path>,
         Q: AsRef<std::path::Path>,
     {
-        tokio::task::block_in_place(|| {
-            let mut ctx = gpgme::Context::from_protocol(gpgme::Protocol::OpenPgp)?;
-            let key = ctx.get_secret_key(self.key)?;
-            ctx.add_signer(&key)?;
-            let reader = std::fs::File::open(path)?;
-            let mut input = gpgme::Data::from_reader(reader)?;
-            let writer = std::fs::File::create(sig_path)?;
-            let mut output = gpgme::Data::from_writer(writer)?;
-            ctx.sign(gpgme::SignMode::Detached, &mut input, &mut output)?;
+        let path = path.as_ref();
+        let sig_path = sig_path.as_ref();
+        let signers = match self.keyring {
+            Some(ref keyring) => verify_with_keyring(keyring, path, sig_path)?,
+            None => verify_with_gpgme(path, sig_path)?,
+        };
+        if signers.is_empty() {
+            return Err(anyhow::anyhow!("No good signature by a known key found"));
+        }
+        if self.fingerprints.is_empty()
+            || signers
+                .iter()
+                .flatten()
+                .any(|fingerprint| self.fingerprints.contains(fingerprint))
+        {
             Ok(())
-        })
+        } else {
+            Err(anyhow::anyhow!(
+                "Not signed by the expected keys but by {}",
+                signers
+                    .iter()
+                    .map(|fingerprints| fingerprints[0].as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        }
+    }
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+// Returns fingerprints of the signing key and its primary key for each good signature
+fn verify_with_gpgme(
+    path: &std::path::Path,
+    sig_path: &std::path::Path,
+) -> Result<Vec<Vec<String>>, anyhow::Error> {
+    let mut ctx = gpgme::Context::from_protocol(gpgme::Protocol::OpenPgp)?;
+    let signed = std::fs::File::open(path)?;
+    let signature = std::fs::File::open(sig_path)?;
+    let result = ctx.verify_detached(signature, signed)?;
+    let mut signers = vec![];
+    for signature in result.signatures() {
+        let fingerprint = signature.fingerprint().unwrap_or("unknown key");
+        match signature.status() {
+            Ok(()) => {}
+            Err(e) if e.code() == gpgme::Error::BAD_SIGNATURE.code() => {
+                return Err(anyhow::anyhow!("Bad signature by {}: {}", fingerprint, e));
+            }
+            Err(e) => {
+                // e.g. NO_PUBKEY, or an expired or revoked key
+                tracing::info!("Skipping signature by {}: {}", fingerprint, e);
+                continue;
+            }
+        }
+        let mut fingerprints = vec![normalize_fingerprint(fingerprint)];
+        if let Ok(key) = ctx.get_key(fingerprint) {
+            if let Ok(primary) = key.fingerprint() {
+                fingerprints.push(normalize_fingerprint(primary));
+            }
+        }
+        signers.push(fingerprints);
+    }
+    Ok(signers)
+}
+
+fn verify_with_keyring(
+    keyring: &[pgp::composed::SignedPublicKey],
+    path: &std::path::Path,
+    sig_path: &std::path::Path,
+) -> Result<Vec<Vec<String>>, anyhow::Error> {
+    let buf = std::fs::read(sig_path)?;
+    let signatures: Vec<pgp::composed::DetachedSignature> = parse_many(&buf)?;
+    let mut signers = vec![];
+    for signature in signatures {
+        let signature = signature.signature;
+        let issuer = signature.issuer_key_id().first().map_or_else(
+            || "unknown key".to_owned(),
+            |id| id.as_ref().iter().map(|b| format!("{:02X}", b)).collect(),
+        );
+        let mut verified = None;
+        for key in keyring {
+            let mut fingerprint = verify_with_key(&key.primary_key, &signature, path)?;
+            for subkey in &key.public_subkeys {
+                if fingerprint.is_some() {
+                    break;
+                }
+                fingerprint = verify_with_key(&subkey.key, &signature, path)?;
+            }
+            if let Some(fingerprint) = fingerprint {
+                use pgp::types::KeyDetails as _;
+                verified = Some(vec![
+                    fingerprint,
+                    format!("{:X}", key.primary_key.fingerprint()),
+                ]);
+                break;
+            }
+        }
+        match verified {
+            Some(fingerprints) => signers.push(fingerprints),
+            None => tracing::info!("Skipping signature by {}: No public key", issuer),
+        }
+    }
+    Ok(signers)
+}
+
+// Returns the fingerprint of `key` if it issued `signature` and the signature is good
+fn verify_with_key<K>(
+    key: &K,
+    signature: &pgp::packet::Signature,
+    path: &std::path::Path,
+) -> Result<Option<String>, anyhow::Error>
+where
+    K: pgp::types::VerifyingKey,
+{
+    let key_fingerprint = key.fingerprint();
+    if !signature.issuer_key_id().contains(&&key.legacy_key_id())
+        && !signature.issuer_fingerprint().contains(&&key_fingerprint)
+    {
+        return Ok(None);
+    }
+    let fingerprint = format!("{:X}", key_fingerprint);
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    signature
+        .verify(key, reader)
+        .map_err(|e| anyhow::anyhow!("Bad signature by {}: {}", fingerprint, e))?;
+    Ok(Some(fingerprint))
+}
+
+/// Append `old_signature` to `signature` to keep old signatures across re-signing, except the
+/// ones issued by the keys that made `signature`
+pub fn append_old_signatures(
+    signature: &[u8],
+    old_signature: &[u8],
+) -> Result<Vec<u8>, anyhow::Error> {
+    use pgp::ser::Serialize as _;
+
+    let new_issuers: Vec<Vec<u8>> = parse_many::<pgp::composed::DetachedSignature>(signature)?
+        .iter()
+        .flat_map(|signature| signature_issuers(&signature.signature))
+        .collect();
+    let mut buf = signature.to_vec();
+    for old in parse_many::<pgp::composed::DetachedSignature>(old_signature)? {
+        // Key IDs are the last 8 bytes of v4 fingerprints
+        let reissued = signature_issuers(&old.signature).iter().any(|old_issuer| {
+            new_issuers
+                .iter()
+                .any(|issuer| issuer.ends_with(old_issuer) || old_issuer.ends_with(issuer))
+        });
+        if !reissued {
+            buf.extend_from_slice(&old.to_bytes()?);
+        }
+    }
+    Ok(buf)
+}
+
+fn signature_issuers(signature: &pgp::packet::Signature) -> Vec<Vec<u8>> {
+    signature
+        .issuer_key_id()
+        .into_iter()
+        .map(|id| id.as_ref().to_vec())
+        .chain(
+            signature
+                .issuer_fingerprint()
+                .into_iter()
+                .map(|fingerprint| fingerprint.as_ref().to_vec()),
+        )
+        .collect()
+}
+
+// Parse concatenated keys or signatures, armored or not
+fn parse_many<T>(buf: &[u8]) -> Result<Vec<T>, anyhow::Error>
+where
+    T: pgp::composed::Deserializable,
+{
+    let items = if buf.starts_with(b"-----BEGIN") {
+        T::from_armor_many(buf)?.0.collect::<Result<Vec<_>, _>>()?
+    } else {
+        T::from_bytes_many(buf)?.collect::<Result<Vec<_>, _>>()?
+    };
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_old_signatures, parse_many, OpenPgpSigner, Signer as _, Verifier};
+
+    fn generate_key() -> pgp::composed::SignedSecretKey {
+        pgp::composed::SecretKeyParamsBuilder::default()
+            .key_type(pgp::composed::KeyType::Ed25519Legacy)
+            .can_certify(true)
+            .can_sign(true)
+            .primary_user_id("guzuta <guzuta@example.com>".to_owned())
+            .build()
+            .unwrap()
+            .generate(rand::thread_rng())
+            .unwrap()
+    }
+
+    fn write_key(key: &pgp::composed::SignedSecretKey, path: &std::path::Path) {
+        std::fs::write(path, key.to_armored_string(Default::default()).unwrap()).unwrap();
+    }
+
+    // Like most release keys, the primary key can only certify and a subkey signs
+    fn generate_key_with_signing_subkey() -> pgp::composed::SignedSecretKey {
+        let subkey = pgp::composed::SubkeyParamsBuilder::default()
+            .key_type(pgp::composed::KeyType::Ed25519Legacy)
+            .can_sign(true)
+            .passphrase(Some("s3cret".to_owned()))
+            .build()
+            .unwrap();
+        pgp::composed::SecretKeyParamsBuilder::default()
+            .key_type(pgp::composed::KeyType::Ed25519Legacy)
+            .can_certify(true)
+            .passphrase(Some("s3cret".to_owned()))
+            .primary_user_id("guzuta <guzuta@example.com>".to_owned())
+            .subkey(subkey)
+            .build()
+            .unwrap()
+            .generate(rand::thread_rng())
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sign_with_subkey() {
+        use pgp::types::KeyDetails as _;
+
+        let key = generate_key_with_signing_subkey();
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("key.asc");
+        write_key(&key, &key_path);
+        let keyring_path = dir.path().join("keyring.asc");
+        std::fs::write(
+            &keyring_path,
+            key.to_public_key()
+                .to_armored_string(Default::default())
+                .unwrap(),
+        )
+        .unwrap();
+        let path = dir.path().join("foo.db");
+        std::fs::write(&path, b"foo").unwrap();
+        let sig_path = dir.path().join("foo.db.sig");
+
+        let mut signer = OpenPgpSigner::load(&key_path).unwrap();
+        signer.set_passphrase("s3cret".to_owned());
+        signer.sign(&path, &sig_path).await.unwrap();
+
+        let signatures: Vec<pgp::composed::DetachedSignature> =
+            parse_many(&std::fs::read(&sig_path).unwrap()).unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(
+            signatures[0].signature.issuer_fingerprint(),
+            vec![&key.secret_subkeys[0].key.fingerprint()]
+        );
+
+        let mut verifier = Verifier::new();
+        verifier.load_keyring(&keyring_path).unwrap();
+        verifier.set_fingerprints(&[format!("{:X}", key.primary_key.fingerprint())]);
+        verifier.verify(&path, &sig_path).unwrap();
+    }
+
+    // Re-signing twice with the same key like `repo-resign --keep-old-signatures`
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_append_old_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_key_path = dir.path().join("old.asc");
+        write_key(&generate_key(), &old_key_path);
+        let new_key_path = dir.path().join("new.asc");
+        write_key(&generate_key(), &new_key_path);
+        let path = dir.path().join("foo.pkg.tar.zst");
+        std::fs::write(&path, b"foo").unwrap();
+        let sig_path = dir.path().join("foo.pkg.tar.zst.sig");
+        let new_sig_path = dir.path().join("foo.pkg.tar.zst.sig.progress");
+
+        OpenPgpSigner::load(&old_key_path)
+            .unwrap()
+            .sign(&path, &sig_path)
+            .await
+            .unwrap();
+        let new_signer = OpenPgpSigner::load(&new_key_path).unwrap();
+        for _ in 0..2 {
+            new_signer.sign(&path, &new_sig_path).await.unwrap();
+            let signature = append_old_signatures(
+                &std::fs::read(&new_sig_path).unwrap(),
+                &std::fs::read(&sig_path).unwrap(),
+            )
+            .unwrap();
+            std::fs::write(&sig_path, signature).unwrap();
+
+            let signatures: Vec<pgp::composed::DetachedSignature> =
+                parse_many(&std::fs::read(&sig_path).unwrap()).unwrap();
+            assert_eq!(signatures.len(), 2);
+        }
     }
 }
@@ -0,0 +1,397 @@
+/// Inconsistency found in a repository
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub pkgname: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.pkgname, self.message)
+    }
+}
+
+/// Check every entry of `repository` against package files in `repo_dir`.
+/// Missing signatures are reported only for entries having `%PGPSIG%` unless `require_signatures`.
+pub fn verify_packages<P>(
+    repository: &super::repository::Repository,
+    repo_dir: P,
+    verifier: &super::signer::Verifier,
+    require_signatures: bool,
+) -> Vec<Problem>
+where
+    P: AsRef<std::path::Path>,
+{
+    let mut entries: Vec<_> = repository.entries().collect();
+    entries.sort_by(|a, b| a.desc.pkgname().cmp(b.desc.pkgname()));
+
+    let mut problems = vec![];
+    for entry in entries {
+        let desc = &entry.desc;
+        let mut report = |message: String| {
+            problems.push(Problem {
+                pkgname: desc.pkgname().to_owned(),
+                message,
+            })
+        };
+
+        let path = repo_dir.as_ref().join(desc.filename());
+        if !path.is_file() {
+            report(format!("{} does not exist", path.display()));
+            continue;
+        }
+        match super::package::Package::load(&path) {
+            Ok(package) => {
+                if package.pkgname() != desc.pkgname() || package.pkgver() != desc.pkgver() {
+                    report(format!(
+                        "{} is {}-{}, not {}-{}",
+                        path.display(),
+                        package.pkgname(),
+                        package.pkgver(),
+                        desc.pkgname(),
+                        desc.pkgver()
+                    ));
+                }
+                if package.csize() != desc.csize() {
+                    report(format!(
+                        "CSIZE mismatch: {} in database, {} actually",
+                        desc.csize(),
+                        package.csize()
+                    ));
+                }
+                if package.md5sum() != desc.md5sum() {
+                    report(format!(
+                        "MD5SUM mismatch: {} in database, {} actually",
+                        desc.md5sum(),
+                        package.md5sum()
+                    ));
+                }
+                if package.sha256sum() != desc.sha256sum() {
+                    report(format!(
+                        "SHA256SUM mismatch: {} in database, {} actually",
+                        desc.sha256sum(),
+                        package.sha256sum()
+                    ));
+                }
+                if package.pgpsig() != desc.pgpsig() {
+                    report("PGPSIG does not match the signature file".to_owned());
+                }
+            }
+            Err(e) => {
+                report(format!("Unable to load {}: {}", path.display(), e));
+            }
+        }
+
+        let mut sig_path = path.clone().into_os_string();
+        sig_path.push(".sig");
+        let sig_path = std::path::PathBuf::from(sig_path);
+        if sig_path.is_file() {
+            if let Err(e) = verifier.verify(&path, &sig_path) {
+                report(format!("Unable to verify {}: {}", sig_path.display(), e));
+            }
+        } else if require_signatures || !desc.pgpsig().is_empty() {
+            report(format!("{} does not exist", sig_path.display()));
+        }
+    }
+    problems
+}
+
+/// Check that `db` and `files` have the same set of packages
+pub fn compare_databases(
+    db: &super::repository::Repository,
+    files: &super::repository::Repository,
+) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (this, other) in [(db, files), (files, db)] {
+        for entry in this.entries() {
+            let desc = &entry.desc;
+            let message = match other.get(desc.pkgname()) {
+                None => format!(
+                    "{} is in {} but not in {}",
+                    desc.pkgver(),
+                    this.path().display(),
+                    other.path().display()
+                ),
+                // Report version mismatches only once
+                Some(_) if std::ptr::eq(this, files) => continue,
+                Some(other_entry) if other_entry.desc.pkgver() != desc.pkgver() => format!(
+                    "{} in {} but {} in {}",
+                    desc.pkgver(),
+                    this.path().display(),
+                    other_entry.desc.pkgver(),
+                    other.path().display()
+                ),
+                Some(_) => continue,
+            };
+            problems.push(Problem {
+                pkgname: desc.pkgname().to_owned(),
+                message,
+            });
+        }
+    }
+    problems.sort_by(|a, b| a.pkgname.cmp(&b.pkgname));
+    problems
+}
+
+/// Check that every `DEPENDS` of `repository` is satisfied by `repository` itself or one of
+/// `sync_repositories`, taking `PROVIDES` and version constraints into account
+pub fn check_depends(
+    repository: &super::repository::Repository,
+    sync_repositories: &[super::repository::Repository],
+) -> Vec<Problem> {
+    let mut entries: Vec<_> = repository.entries().collect();
+    entries.sort_by(|a, b| a.desc.pkgname().cmp(b.desc.pkgname()));
+
+    let mut problems = vec![];
+    for entry in entries {
+        for depend in entry.desc.parsed_depends() {
+            let resolved = std::iter::once(repository)
+                .chain(sync_repositories)
+                .any(|r| r.satisfiers(&depend).next().is_some());
+            if !resolved {
+                problems.push(Problem {
+                    pkgname: entry.desc.pkgname().to_owned(),
+                    message: format!("unresolved dependency {}", depend),
+                });
+            }
+        }
+    }
+    problems
+}
+
+/// Check that soname provides of shared libraries in `package` are in its provides, and that
+/// its DT_NEEDED entries are resolved by `repositories`, either by provides like `libbar.so=2-64`
+/// or by shipping the library in case of files databases. Providers of needed sonames must be
+/// in depends. Needed sonames are not checked without `repositories`.
+/// `package` must be loaded by `Package::load_with_elf`.
+pub fn check_sonames(
+    package: &super::package::Package,
+    repositories: &[super::repository::Repository],
+) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut report = |message: String| {
+        problems.push(Problem {
+            pkgname: package.pkgname().to_owned(),
+            message,
+        })
+    };
+
+    let provides = package.parsed_provides();
+    for provide in package.soname_provides() {
+        if !provides
+            .iter()
+            .any(|p| p.name() == provide.name() && p.version() == provide.version())
+        {
+            report(format!("{} is not in provides", provide));
+        }
+    }
+
+    if repositories.is_empty() {
+        return problems;
+    }
+    let depends = package.parsed_depends();
+    for (soname, bits) in package.needed_sonames() {
+        let depend = super::package::soname_depend(soname, bits);
+        let providers: Vec<_> = depend
+            .iter()
+            .flat_map(|depend| {
+                repositories
+                    .iter()
+                    .flat_map(move |repository| repository.satisfiers(depend))
+            })
+            .collect();
+        if let (Some(depend), false) = (&depend, providers.is_empty()) {
+            let depended = depends.iter().any(|d| {
+                d.name() == depend.name()
+                    || providers.iter().any(|entry| {
+                        entry.desc.pkgname() == d.name()
+                            || entry
+                                .desc
+                                .parsed_provides()
+                                .iter()
+                                .any(|provide| provide.name() == d.name())
+                    })
+            });
+            if !depended {
+                let mut names: Vec<_> = providers.iter().map(|e| e.desc.pkgname()).collect();
+                names.sort_unstable();
+                names.dedup();
+                report(format!(
+                    "{} provided by {} is not in depends",
+                    depend,
+                    names.join(", ")
+                ));
+            }
+        } else if !repositories.iter().any(|repository| {
+            !repository
+                .search_files(super::repository::FileQuery::Basename(soname))
+                .is_empty()
+        }) {
+            report(format!("{} is not provided by any package", soname));
+        }
+    }
+    problems
+}
+
+/// Check members of the package file at `path` against .MTREE of `package` by re-hashing them
+pub fn verify_package_contents<P>(
+    package: &super::package::Package,
+    path: P,
+) -> Result<Vec<Problem>, anyhow::Error>
+where
+    P: AsRef<std::path::Path>,
+{
+    use md5::Digest as _;
+    use std::io::Read as _;
+
+    let mut problems = vec![];
+    let mut report = |message: String| {
+        problems.push(Problem {
+            pkgname: package.pkgname().to_owned(),
+            message,
+        })
+    };
+    let mtree = match (package.mtree(), package.mtree_error()) {
+        (Some(mtree), _) => mtree,
+        (None, Some(error)) => {
+            report(format!(".MTREE cannot be parsed: {}", error));
+            return Ok(problems);
+        }
+        (None, None) => {
+            report(".MTREE does not exist".to_owned());
+            return Ok(problems);
+        }
+    };
+    let mut unseen: std::collections::HashMap<&std::path::Path, &super::package::MtreeEntry> =
+        mtree
+            .iter()
+            .map(|entry| (entry.path.as_path(), entry))
+            .collect();
+
+    let file = std::fs::File::open(path.as_ref())?;
+    let mut tar_reader = tar::Archive::new(super::compression::decompress(file)?);
+    for entry_result in tar_reader.entries()? {
+        let mut entry = entry_result?;
+        let member_path = entry.path()?.into_owned();
+        if member_path.as_os_str() == ".MTREE" {
+            continue;
+        }
+        let name = member_path.display();
+        let expected = match unseen.remove(member_path.as_path()) {
+            Some(expected) => expected,
+            None => {
+                report(format!("{} is not listed in .MTREE", name));
+                continue;
+            }
+        };
+
+        let header = entry.header();
+        // bsdtar records hard links as type=file, whose contents are stored in another member
+        let is_hard_link = header.entry_type() == tar::EntryType::Link;
+        let file_type = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Link => super::package::MtreeType::File,
+            tar::EntryType::Directory => super::package::MtreeType::Dir,
+            tar::EntryType::Symlink => super::package::MtreeType::Link,
+            other => super::package::MtreeType::Other(format!("{:?}", other)),
+        };
+        if file_type != expected.file_type {
+            report(format!(
+                "{}: type mismatch: {} in .MTREE, {} actually",
+                name, expected.file_type, file_type
+            ));
+            continue;
+        }
+        let mode = header.mode()? & 0o7777;
+        if let Some(expected_mode) = expected.mode.filter(|m| *m != mode) {
+            report(format!(
+                "{}: mode mismatch: {:o} in .MTREE, {:o} actually",
+                name, expected_mode, mode
+            ));
+        }
+        for (key, expected_id, id) in [
+            ("uid", expected.uid, header.uid()?),
+            ("gid", expected.gid, header.gid()?),
+        ] {
+            if let Some(expected_id) = expected_id.filter(|i| *i != id) {
+                report(format!(
+                    "{}: {} mismatch: {} in .MTREE, {} actually",
+                    name, key, expected_id, id
+                ));
+            }
+        }
+        let mtime = header.mtime()?;
+        if let Some(expected_mtime) = expected.mtime.filter(|t| *t != mtime) {
+            report(format!(
+                "{}: time mismatch: {} in .MTREE, {} actually",
+                name, expected_mtime, mtime
+            ));
+        }
+
+        match file_type {
+            super::package::MtreeType::Link => {
+                let link = entry.link_name()?.unwrap_or_default().into_owned();
+                if let Some(expected_link) = expected.link.as_ref().filter(|l| **l != link) {
+                    report(format!(
+                        "{}: link mismatch: {} in .MTREE, {} actually",
+                        name,
+                        expected_link.display(),
+                        link.display()
+                    ));
+                }
+            }
+            super::package::MtreeType::File if !is_hard_link => {
+                let mut md5 = md5::Md5::new();
+                let mut sha256 = sha2::Sha256::new();
+                let mut size = 0;
+                let mut buf = [0; 8192];
+                loop {
+                    match entry.read(&mut buf)? {
+                        0 => break,
+                        len => {
+                            md5.update(&buf[..len]);
+                            sha256.update(&buf[..len]);
+                            size += len as u64;
+                        }
+                    }
+                }
+                if let Some(expected_size) = expected.size.filter(|s| *s != size) {
+                    report(format!(
+                        "{}: size mismatch: {} in .MTREE, {} actually",
+                        name, expected_size, size
+                    ));
+                }
+                let md5digest = format!("{:x}", md5.finalize());
+                if let Some(expected_digest) =
+                    expected.md5digest.as_ref().filter(|d| **d != md5digest)
+                {
+                    report(format!(
+                        "{}: MD5 mismatch: {} in .MTREE, {} actually",
+                        name, expected_digest, md5digest
+                    ));
+                }
+                let sha256digest = format!("{:x}", sha256.finalize());
+                if let Some(expected_digest) = expected
+                    .sha256digest
+                    .as_ref()
+                    .filter(|d| **d != sha256digest)
+                {
+                    report(format!(
+                        "{}: SHA256 mismatch: {} in .MTREE, {} actually",
+                        name, expected_digest, sha256digest
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut missing: Vec<_> = unseen.into_keys().collect();
+    missing.sort();
+    for path in missing {
+        report(format!(
+            "{} is listed in .MTREE but not in the package",
+            path.display()
+        ));
+    }
+    Ok(problems)
+}
@@ -0,0 +1,268 @@
+/// Package version in the form of `[epoch:]pkgver[-pkgrel]`, ordered like alpm
+#[derive(Debug, Clone)]
+pub struct Version {
+    epoch: String,
+    pkgver: String,
+    pkgrel: Option<String>,
+}
+
+impl Version {
+    /// Split `version` into epoch, pkgver and pkgrel in the same way as alpm's parseEVR
+    pub fn parse(version: &str) -> Self {
+        let digits = version
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(version.len());
+        let (epoch, rest) = if version[digits..].starts_with(':') {
+            let epoch = &version[..digits];
+            (
+                if epoch.is_empty() { "0" } else { epoch },
+                &version[digits + 1..],
+            )
+        } else {
+            ("0", version)
+        };
+        let (pkgver, pkgrel) = match rest.rfind('-') {
+            Some(i) => (&rest[..i], Some(rest[i + 1..].to_owned())),
+            None => (rest, None),
+        };
+        Version {
+            epoch: epoch.to_owned(),
+            pkgver: pkgver.to_owned(),
+            pkgrel,
+        }
+    }
+
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+    pub fn pkgver(&self) -> &str {
+        &self.pkgver
+    }
+    pub fn pkgrel(&self) -> Option<&str> {
+        self.pkgrel.as_deref()
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        if self.epoch != "0" {
+            write!(f, "{}:", self.epoch)?;
+        }
+        self.pkgver.fmt(f)?;
+        if let Some(ref pkgrel) = self.pkgrel {
+            write!(f, "-{}", pkgrel)?;
+        }
+        Ok(())
+    }
+}
+
+impl Version {
+    /// Compare like alpm, where pkgrel is compared only when both versions have it. This is not
+    /// a total order, e.g. 1.0-1 == 1.0 == 1.0-2 but 1.0-1 < 1.0-2.
+    pub fn alpm_cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.cmp_epoch_pkgver(other)
+            .then_with(|| match (&self.pkgrel, &other.pkgrel) {
+                (Some(a), Some(b)) => rpmvercmp(a, b),
+                _ => std::cmp::Ordering::Equal,
+            })
+    }
+
+    fn cmp_epoch_pkgver(&self, other: &Self) -> std::cmp::Ordering {
+        rpmvercmp(&self.epoch, &other.epoch).then_with(|| rpmvercmp(&self.pkgver, &other.pkgver))
+    }
+}
+
+/// Total order where a version without pkgrel comes before the ones with pkgrel. Use
+/// `Version::alpm_cmp` or `vercmp` to ignore the missing pkgrel like alpm.
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.cmp_epoch_pkgver(other)
+            .then_with(|| match (&self.pkgrel, &other.pkgrel) {
+                (Some(a), Some(b)) => rpmvercmp(a, b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// Compare two full version strings like `vercmp(8)`
+pub fn vercmp(a: &str, b: &str) -> std::cmp::Ordering {
+    if a == b {
+        std::cmp::Ordering::Equal
+    } else {
+        Version::parse(a).alpm_cmp(&Version::parse(b))
+    }
+}
+
+// Port of rpmvercmp in libalpm/version.c
+fn rpmvercmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    if a == b {
+        return Ordering::Equal;
+    }
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    let (mut one, mut two) = (0, 0);
+    let (mut ptr1, mut ptr2) = (0, 0);
+
+    while one < a.len() && two < b.len() {
+        while one < a.len() && !a[one].is_ascii_alphanumeric() {
+            one += 1;
+        }
+        while two < b.len() && !b[two].is_ascii_alphanumeric() {
+            two += 1;
+        }
+        if one >= a.len() || two >= b.len() {
+            break;
+        }
+        // A longer run of separators wins
+        if one - ptr1 != two - ptr2 {
+            return (one - ptr1).cmp(&(two - ptr2));
+        }
+
+        ptr1 = one;
+        ptr2 = two;
+        let is_num = a[ptr1].is_ascii_digit();
+        let same_class = |c: u8| {
+            if is_num {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        while ptr1 < a.len() && same_class(a[ptr1]) {
+            ptr1 += 1;
+        }
+        while ptr2 < b.len() && same_class(b[ptr2]) {
+            ptr2 += 1;
+        }
+
+        if two == ptr2 {
+            // Numeric segments are newer than alpha ones
+            return if is_num {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let mut seg1 = &a[one..ptr1];
+        let mut seg2 = &b[two..ptr2];
+        if is_num {
+            while let [b'0', rest @ ..] = seg1 {
+                seg1 = rest;
+            }
+            while let [b'0', rest @ ..] = seg2 {
+                seg2 = rest;
+            }
+            match seg1.len().cmp(&seg2.len()) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        match seg1.cmp(seg2) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+
+        one = ptr1;
+        two = ptr2;
+    }
+
+    if one >= a.len() && two >= b.len() {
+        Ordering::Equal
+    } else if (one >= a.len() && !b[two].is_ascii_alphabetic())
+        || (one < a.len() && a[one].is_ascii_alphabetic())
+    {
+        // A remaining alpha segment never beats an empty string
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{vercmp, Version};
+    use std::cmp::Ordering;
+
+    fn assert_vercmp(a: &str, b: &str, expected: Ordering) {
+        assert_eq!(vercmp(a, b), expected, "vercmp({}, {})", a, b);
+        assert_eq!(vercmp(b, a), expected.reverse(), "vercmp({}, {})", b, a);
+    }
+
+    // Cases from pacman's test/util/vercmptest.sh
+    #[test]
+    fn test_vercmp() {
+        for &(a, b, expected) in &[
+            ("1.5.0", "1.5.0", Ordering::Equal),
+            ("1.5.1", "1.5.0", Ordering::Greater),
+            ("1.5.1", "1.5", Ordering::Greater),
+            ("1.5.0-1", "1.5.0-2", Ordering::Less),
+            ("1.5-2", "1.5.1-1", Ordering::Less),
+            ("1.0a", "1.0", Ordering::Less),
+            ("1.5b-1", "1.5", Ordering::Less),
+            ("1.5b", "1.5.1", Ordering::Less),
+            ("1.0a", "1.0alpha", Ordering::Less),
+            ("1.0alpha", "1.0b", Ordering::Less),
+            ("1.0b", "1.0beta", Ordering::Less),
+            ("1.0beta", "1.0rc", Ordering::Less),
+            ("1.0rc", "1.0", Ordering::Less),
+            ("alpha", "beta", Ordering::Less),
+            ("1.5.a", "1.5", Ordering::Greater),
+            ("1.5.b", "1.5.a", Ordering::Greater),
+            ("1.5.1", "1.5.b", Ordering::Greater),
+            ("1.5-1", "1.5.b", Ordering::Less),
+            ("2.0", "2_0", Ordering::Equal),
+            ("2.0_a", "2_0.a", Ordering::Equal),
+            ("2.0a", "2.0.a", Ordering::Less),
+            ("2___a", "2_a", Ordering::Greater),
+            ("0:1.0", "1.0", Ordering::Equal),
+            ("1:1.0", "0:1.1", Ordering::Greater),
+            ("1:1.0", "2:1.1", Ordering::Less),
+            ("1:0", "2", Ordering::Greater),
+            ("1:1.0", "0:1.0-1", Ordering::Greater),
+            ("1:1.0-1", "0:1.1-1", Ordering::Greater),
+        ] {
+            assert_vercmp(a, b, expected);
+        }
+    }
+
+    #[test]
+    fn test_vercmp_missing_pkgrel() {
+        assert_vercmp("1.0-1", "1.0", Ordering::Equal);
+        assert_vercmp("1.0", "1.0-2", Ordering::Equal);
+        assert_vercmp("1.0-1", "1.0-2", Ordering::Less);
+        assert_vercmp("1.5.b-1", "1.5.b", Ordering::Equal);
+        assert_vercmp("1.0-1", "1.1", Ordering::Less);
+    }
+
+    #[test]
+    fn test_ord_is_total() {
+        let v = |s: &str| Version::parse(s);
+        assert!(v("1.0") < v("1.0-1"));
+        assert!(v("1.0-1") < v("1.0-2"));
+        assert!(v("1.0") < v("1.0-2"));
+        assert_ne!(v("1.0"), v("1.0-1"));
+        assert_eq!(v("0:1.0-1"), v("1.0-1"));
+
+        let mut versions = [v("1.0-2"), v("1.0"), v("1.1"), v("1.0-1"), v("1:0.1")];
+        versions.sort();
+        let sorted: Vec<_> = versions.iter().map(|v| v.to_string()).collect();
+        assert_eq!(sorted, ["1.0", "1.0-1", "1.0-2", "1.1", "1:0.1"]);
+    }
+}